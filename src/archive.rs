@@ -0,0 +1,124 @@
+use crate::journal::Operation;
+use crate::metadata::ProjectState;
+use crate::{Environment, Project};
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::path::Path;
+
+/// Prefix of the temporary files and directories in the raw data directory while a project is
+/// compressed or extracted, which are ignored when listing projects
+const TEMPORARY_PREFIX: &str = ".archive-";
+
+/// Moves `project` out of the normal views and into the archive schemas. `compression` replaces
+/// its data directory by a tarball if set, or extracts a compressed project again if unset; the
+/// data is left as it is without it. Returns the operations reversing this, along with the error
+/// if only some of the changes could be made.
+pub fn archive(project: &Project, compression: Option<bool>) -> (Vec<Operation>, Result<()>) {
+    let mut operations = vec![];
+    let result = archive_each(project, compression, &mut operations);
+    (operations, result)
+}
+
+fn archive_each(
+    project: &Project,
+    compression: Option<bool>,
+    operations: &mut Vec<Operation>,
+) -> Result<()> {
+    operations.extend(project.update_metadata(|metadata| metadata.state = ProjectState::Archived)?);
+
+    match compression {
+        Some(true) if !project.compressed => {
+            compress_data(project)?;
+            operations.push(Operation::Compress { uuid: project.uuid });
+        }
+        Some(false) if project.compressed => {
+            extract_data(project)?;
+            operations.push(Operation::Extract { uuid: project.uuid });
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns `project` to the normal views, extracting its data if it is compressed. Returns the
+/// operations reversing this, along with the error if only some of the changes could be made.
+pub fn unarchive(project: &Project) -> (Vec<Operation>, Result<()>) {
+    let mut operations = vec![];
+    let result = unarchive_each(project, &mut operations);
+    (operations, result)
+}
+
+fn unarchive_each(project: &Project, operations: &mut Vec<Operation>) -> Result<()> {
+    if project.compressed {
+        extract_data(project)?;
+        operations.push(Operation::Extract { uuid: project.uuid });
+    }
+
+    operations.extend(project.update_metadata(|metadata| metadata.state = ProjectState::Active)?);
+    Ok(())
+}
+
+/// Replaces the data directory of `project` by a zstd compressed tarball. The directory is moved
+/// out of the project first, so nothing can change it while it is compressed, and only removed
+/// once the tarball is in place.
+pub fn compress_data(project: &Project) -> Result<()> {
+    let env = Environment::get()?;
+    let data_path = project.get_project_raw_data_path()?;
+    if !data_path.is_dir() {
+        return Err(anyhow!("Project {} is already compressed", project.name));
+    }
+
+    let temporary_dir =
+        env.raw_storage_dir
+            .join(format!("{}{}", TEMPORARY_PREFIX, project.uuid.hyphenated()));
+    let temporary_path = temporary_dir.with_extension("tar.zst");
+    std::fs::rename(&data_path, &temporary_dir)?;
+
+    if let Err(e) = write_tarball(&temporary_dir, &project.name, &temporary_path) {
+        // Put the data back where it was
+        let _ = std::fs::remove_file(&temporary_path);
+        std::fs::rename(&temporary_dir, &data_path)?;
+        return Err(e);
+    }
+
+    std::fs::rename(&temporary_path, project.get_compressed_data_path()?)?;
+    std::fs::remove_dir_all(&temporary_dir)?;
+
+    Ok(())
+}
+
+/// Writes `dir` into a zstd compressed tarball at `path`, with its contents below `name`
+fn write_tarball(dir: &Path, name: &str, path: &Path) -> Result<()> {
+    let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(path)?, 0)?);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(name, dir)?;
+    builder.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Replaces the tarball of a compressed project by its data directory
+pub fn extract_data(project: &Project) -> Result<()> {
+    let env = Environment::get()?;
+    let compressed_path = project.get_compressed_data_path()?;
+    if !compressed_path.is_file() {
+        return Err(anyhow!("Project {} is not compressed", project.name));
+    }
+
+    // Extract next to the project first, so a failure doesn't leave half of the data behind
+    let temporary_dir =
+        env.raw_storage_dir
+            .join(format!("{}{}", TEMPORARY_PREFIX, project.uuid.hyphenated()));
+    let mut archive = tar::Archive::new(zstd::Decoder::new(File::open(&compressed_path)?)?);
+    archive.set_preserve_permissions(true);
+    archive.unpack(&temporary_dir)?;
+
+    std::fs::rename(
+        temporary_dir.join(&project.name),
+        project.get_project_raw_data_path()?,
+    )?;
+    std::fs::remove_dir(&temporary_dir)?;
+    std::fs::remove_file(&compressed_path)?;
+
+    Ok(())
+}
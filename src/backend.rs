@@ -0,0 +1,262 @@
+use crate::file_utils::{remove_symlink_dir, symlink_dir};
+use crate::schema::LinkStyle;
+use crate::weave::{managed_link_target, raw_data_target, ViewLink};
+use crate::Environment;
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const REDIRECT_TARGET_KEY: &str = "X-Spiderman-Target=";
+#[cfg(unix)]
+const REDIRECT_FILE_EXTENSION: &str = "desktop";
+#[cfg(windows)]
+const REDIRECT_FILE_EXTENSION: &str = "url";
+/// Redirect files are small, only this much of a file is read to recognise one
+const MAX_REDIRECT_FILE_SIZE: u64 = 64 * 1024;
+const VIEW_INDEX_FILE_NAME: &str = "views.tsv";
+
+/// Selects how the links of the view tree are materialised on disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkBackendKind {
+    /// Symlinks to the project data directories
+    #[default]
+    Symlink,
+    /// Small shortcut files (`.desktop` on Unix, `.url` on Windows) pointing to the project data
+    Redirect,
+    /// No view tree at all, only an index of view paths in the spiderman directory
+    Index,
+}
+
+impl LinkBackendKind {
+    pub const ALL: [LinkBackendKind; 3] = [Self::Symlink, Self::Redirect, Self::Index];
+
+    pub fn backend(&self, link_style: LinkStyle) -> Box<dyn LinkBackend> {
+        match self {
+            Self::Symlink => Box::new(SymlinkBackend { link_style }),
+            Self::Redirect => Box::new(RedirectBackend),
+            Self::Index => Box::new(IndexBackend),
+        }
+    }
+}
+
+/// Creates and removes the entries of the view tree.
+///
+/// Every backend must recognise the entries it created, so that weaving never removes anything
+/// in the view tree it is not responsible for.
+pub trait LinkBackend: Sync {
+    /// Materialises all given links.
+    ///
+    /// Directories are handled in parallel, but the links within one directory are created one
+    /// after another in the given order.
+    fn create_links(&self, links: &[ViewLink]) -> Result<()> {
+        let mut links_by_dir: BTreeMap<&Path, Vec<&ViewLink>> = BTreeMap::new();
+        for link in links {
+            links_by_dir
+                .entry(link.link.parent().unwrap())
+                .or_default()
+                .push(link);
+        }
+
+        links_by_dir.into_par_iter().try_for_each(|(dir, links)| {
+            std::fs::create_dir_all(dir)?;
+            links.into_iter().try_for_each(|l| self.create(l))
+        })
+    }
+
+    /// Materialises a single link
+    fn create(&self, link: &ViewLink) -> Result<()>;
+
+    /// Path of the entry created for a link at `link`
+    fn entry_path(&self, link: &Path) -> PathBuf {
+        link.to_path_buf()
+    }
+
+    /// Returns whether `path` is an entry of the view tree created by this backend
+    fn is_managed(&self, path: &Path) -> Result<bool>;
+
+    /// Removes an entry for which [`LinkBackend::is_managed`] returned true
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Removes everything this backend created outside of the view tree
+    fn clear(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct SymlinkBackend {
+    link_style: LinkStyle,
+}
+
+impl LinkBackend for SymlinkBackend {
+    fn create(&self, link: &ViewLink) -> Result<()> {
+        let link_dir = link.link.parent().unwrap();
+        let source = match self.link_style {
+            LinkStyle::Absolute => link.target.clone(),
+            LinkStyle::Relative => pathdiff::diff_paths(&link.target, link_dir).ok_or(anyhow!(
+                "Can't express {} relative to {}",
+                link.target.to_string_lossy(),
+                link_dir.to_string_lossy()
+            ))?,
+        };
+
+        symlink_dir(&source, &link.link)
+    }
+
+    fn is_managed(&self, path: &Path) -> Result<bool> {
+        Ok(path.is_symlink() && managed_link_target(path)?.is_some())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        remove_symlink_dir(path)
+    }
+}
+
+pub struct RedirectBackend;
+
+impl LinkBackend for RedirectBackend {
+    fn create(&self, link: &ViewLink) -> Result<()> {
+        let name = link
+            .link
+            .file_name()
+            .ok_or(anyhow!("Link path has no name"))?
+            .to_string_lossy();
+        let url = file_url(&link.target);
+        let target = link.target.to_string_lossy();
+
+        #[cfg(unix)]
+        let contents = format!(
+            "[Desktop Entry]\nType=Link\nName={}\nURL={}\n{}{}\n",
+            name, url, REDIRECT_TARGET_KEY, target
+        );
+        #[cfg(windows)]
+        let contents = format!(
+            "[InternetShortcut]\r\nURL={}\r\n{}{}\r\n",
+            url, REDIRECT_TARGET_KEY, target
+        );
+
+        File::create(self.entry_path(&link.link))?.write_all(contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn entry_path(&self, link: &Path) -> PathBuf {
+        let mut path = link.as_os_str().to_owned();
+        path.push(".");
+        path.push(REDIRECT_FILE_EXTENSION);
+        PathBuf::from(path)
+    }
+
+    fn is_managed(&self, path: &Path) -> Result<bool> {
+        if path.is_symlink()
+            || !path.is_file()
+            || path.extension() != Some(OsStr::new(REDIRECT_FILE_EXTENSION))
+        {
+            return Ok(false);
+        }
+
+        // Any file with the extension may be the user's own, so a file that can't be read or isn't
+        // one of ours is simply left alone
+        let mut contents = vec![];
+        let read = File::open(path)
+            .and_then(|f| f.take(MAX_REDIRECT_FILE_SIZE).read_to_end(&mut contents));
+        if read.is_err() {
+            return Ok(false);
+        }
+
+        let target = String::from_utf8_lossy(&contents)
+            .lines()
+            .find_map(|l| l.trim_end().strip_prefix(REDIRECT_TARGET_KEY))
+            .map(PathBuf::from);
+        Ok(target.is_some_and(|t| raw_data_target(&t).is_ok_and(|t| t.is_some())))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path)?;
+
+        Ok(())
+    }
+}
+
+pub struct IndexBackend;
+
+impl IndexBackend {
+    fn index_path() -> Result<PathBuf> {
+        Ok(Environment::get()?.spiderman_dir.join(VIEW_INDEX_FILE_NAME))
+    }
+
+    /// Line of the index for `link`, with its path relative to the project root
+    fn line(link: &ViewLink, base_path: &Path) -> String {
+        let view_path = link.link.strip_prefix(base_path).unwrap_or(&link.link);
+        format!(
+            "{}\t{}\t{}\n",
+            view_path.to_string_lossy(),
+            link.uuid.hyphenated(),
+            link.target.to_string_lossy()
+        )
+    }
+}
+
+impl LinkBackend for IndexBackend {
+    fn create_links(&self, links: &[ViewLink]) -> Result<()> {
+        let base_path = &Environment::get()?.base_path;
+        let index: String = links.iter().map(|l| Self::line(l, base_path)).collect();
+        File::create(Self::index_path()?)?.write_all(index.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn create(&self, link: &ViewLink) -> Result<()> {
+        let line = Self::line(link, &Environment::get()?.base_path);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::index_path()?)?
+            .write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    fn is_managed(&self, _path: &Path) -> Result<bool> {
+        // The index backend never writes into the view tree
+        Ok(false)
+    }
+
+    fn remove(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        let index_path = Self::index_path()?;
+        if index_path.exists() {
+            std::fs::remove_file(index_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats `path` as a `file://` URL, percent-encoding everything but unreserved characters
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from("file://");
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    url
+}
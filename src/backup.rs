@@ -0,0 +1,277 @@
+use crate::file_utils::write_atomically;
+use crate::journal::{Operation, Plan};
+use crate::metadata::{is_project_file, project_file, METADATA_FILE_NAME, TAGS_FILE_NAME};
+use crate::project::COMPRESSED_DATA_EXTENSION;
+use crate::{Environment, Project};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const BACKUP_DIR_NAME: &str = "backups";
+
+/// Copy of all metadata of a project root at one point in time. Project data is not included.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub created: DateTime<Local>,
+    /// Contents of `schema.toml`
+    pub schema: String,
+    #[serde(default)]
+    pub projects: Vec<ProjectSnapshot>,
+}
+
+/// The tags file of a project, along with the name the project had at the time
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectSnapshot {
+    pub uuid: Uuid,
+    pub name: String,
+    /// Either `spiderman.tags` or `spiderman.toml`
+    pub file_name: String,
+    pub contents: String,
+}
+
+/// The files restoring a [`Snapshot`] will modify, computed before anything is written
+pub struct RestorePlan {
+    path: PathBuf,
+    schema: Option<(String, String)>,
+    projects: Vec<(Uuid, String, ProjectSnapshot)>,
+}
+
+fn backup_dir() -> Result<PathBuf> {
+    Ok(Environment::get()?.spiderman_dir.join(BACKUP_DIR_NAME))
+}
+
+/// Writes a snapshot of `schema.toml` and the tags files of all projects into the backup
+/// directory and returns its path. The tags files are copied as they are, so projects whose tags
+/// don't parse are backed up too.
+pub fn backup() -> Result<PathBuf> {
+    let env = Environment::get()?;
+
+    let mut projects = vec![];
+    for entry in env.raw_storage_dir.read_dir()? {
+        let dir = entry?.path();
+        let dir_name = dir.file_name().unwrap().to_string_lossy().to_string();
+        if dir_name.starts_with('.') || !dir.is_dir() {
+            continue;
+        }
+        let Ok(uuid) = Uuid::parse_str(&dir_name) else {
+            eprintln!(
+                "WARNING: {} is not a project directory, skipping it",
+                dir.to_string_lossy()
+            );
+            continue;
+        };
+
+        let path = project_file(&dir);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            eprintln!(
+                "WARNING: {} has no tags file, skipping it",
+                dir.to_string_lossy()
+            );
+            continue;
+        };
+        projects.push(ProjectSnapshot {
+            uuid,
+            name: data_name(&dir)?,
+            file_name: path.file_name().unwrap().to_string_lossy().to_string(),
+            contents,
+        });
+    }
+
+    let snapshot = Snapshot {
+        created: Local::now(),
+        schema: std::fs::read_to_string(env.schema_file_path())?,
+        projects,
+    };
+
+    let dir = backup_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let path = reserve_snapshot_path(&dir, &snapshot.created)?;
+    write_atomically(&path, &toml::ser::to_vec(&snapshot)?)?;
+
+    Ok(path)
+}
+
+/// Name of the project in the UUID directory `dir`, taken from its data directory or tarball
+fn data_name(dir: &Path) -> Result<String> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if is_project_file(&file_name) {
+            continue;
+        }
+        return Ok(match file_name.strip_suffix(COMPRESSED_DATA_EXTENSION) {
+            Some(name) if entry.file_type()?.is_file() => name.to_string(),
+            _ => file_name,
+        });
+    }
+
+    Ok(String::new())
+}
+
+/// Creates an empty file named after `created` in `dir`, with a counter if several snapshots are
+/// taken within the same second
+fn reserve_snapshot_path(dir: &Path, created: &DateTime<Local>) -> Result<PathBuf> {
+    let timestamp = created.format("%Y-%m-%d_%H-%M-%S").to_string();
+    for counter in 1.. {
+        let file_name = match counter {
+            1 => format!("{}.toml", timestamp),
+            _ => format!("{}_{}.toml", timestamp, counter),
+        };
+        let path = dir.join(file_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Resolves `snapshot` as a path, or else as the name of a file in the backup directory
+fn snapshot_path(snapshot: &Path) -> Result<PathBuf> {
+    if snapshot.is_file() {
+        return Ok(snapshot.to_path_buf());
+    }
+
+    let path = backup_dir()?.join(snapshot);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(anyhow!("No snapshot {}", snapshot.to_string_lossy()))
+    }
+}
+
+impl RestorePlan {
+    pub fn new(snapshot: &Path) -> Result<Self> {
+        let env = Environment::get()?;
+        let path = snapshot_path(snapshot)?;
+        let snapshot: Snapshot = toml::de::from_slice(&std::fs::read(&path)?)
+            .with_context(|| format!("Failed to parse snapshot {}", path.to_string_lossy()))?;
+
+        let current_schema = std::fs::read_to_string(env.schema_file_path())?;
+        let schema =
+            (current_schema != snapshot.schema).then_some((current_schema, snapshot.schema));
+
+        let mut projects = vec![];
+        for project in snapshot.projects {
+            let project_dir = env
+                .raw_storage_dir
+                .join(project.uuid.hyphenated().to_string());
+            if !project_dir.is_dir() {
+                eprintln!(
+                    "WARNING: Project {} ({}) does not exist anymore, skipping it",
+                    project.name,
+                    project.uuid.hyphenated()
+                );
+                continue;
+            }
+
+            let current_file = project_file(&project_dir);
+            let unchanged = current_file.ends_with(&project.file_name)
+                && std::fs::read_to_string(&current_file).ok().as_ref() == Some(&project.contents);
+            if unchanged {
+                continue;
+            }
+
+            // Projects are told apart by their UUID, a project renamed since keeps its new name
+            let name = match Project::read(&project_dir) {
+                Ok(current) if !project.name.is_empty() && current.name != project.name => {
+                    format!("{} (then {})", current.name, project.name)
+                }
+                _ => project.name.clone(),
+            };
+            projects.push((project.uuid, name, project));
+        }
+
+        Ok(Self {
+            path,
+            schema,
+            projects,
+        })
+    }
+}
+
+impl Plan for RestorePlan {
+    fn is_empty(&self) -> bool {
+        self.schema.is_none() && self.projects.is_empty()
+    }
+
+    fn print_preview(&self) {
+        if self.schema.is_some() {
+            println!("schema.toml");
+        }
+        for (uuid, name, _) in &self.projects {
+            println!("{} ({})", name, &uuid.hyphenated().to_string()[..8]);
+        }
+    }
+
+    fn confirmation(&self) -> String {
+        "Restore these files?".to_string()
+    }
+
+    fn description(&self) -> String {
+        format!("restore-metadata {}", self.path.to_string_lossy())
+    }
+
+    fn apply_each(&self, operations: &mut Vec<Operation>) -> Result<()> {
+        let env = Environment::get()?;
+
+        if let Some((current, restored)) = &self.schema {
+            write_atomically(&env.schema_file_path(), restored.as_bytes())?;
+            operations.push(Operation::EditSchema {
+                previous: current.clone(),
+            });
+        }
+
+        for (uuid, name, project) in &self.projects {
+            let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+            let current_file = project_file(&project_dir);
+            let (file_name, previous) = match std::fs::read_to_string(&current_file) {
+                Ok(contents) => (
+                    Some(
+                        current_file
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    ),
+                    contents,
+                ),
+                Err(_) => (None, String::new()),
+            };
+
+            restore_project_file(&project_dir, &project.file_name, &project.contents)
+                .with_context(|| format!("Failed to restore the tags of {}", name))?;
+            operations.push(Operation::RestoreProjectFile {
+                uuid: *uuid,
+                file_name,
+                previous,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces the tags file of the project in `project_dir` by `file_name` with `contents`,
+/// removing a file of the other format
+pub fn restore_project_file(project_dir: &Path, file_name: &str, contents: &str) -> Result<()> {
+    if file_name != TAGS_FILE_NAME && file_name != METADATA_FILE_NAME {
+        return Err(anyhow!("{} is not a tags file", file_name));
+    }
+
+    write_atomically(&project_dir.join(file_name), contents.as_bytes())?;
+    for other in [TAGS_FILE_NAME, METADATA_FILE_NAME] {
+        let path = project_dir.join(other);
+        if other != file_name && path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
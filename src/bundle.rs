@@ -0,0 +1,139 @@
+use crate::metadata::project_file;
+use crate::transfer::STAGING_DIR_PREFIX;
+use crate::{Environment, Project};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Name of the manifest, the first entry of every bundle
+const MANIFEST_FILE_NAME: &str = "spiderman-bundle.toml";
+const BUNDLE_FORMAT: u32 = 1;
+
+/// Describes the project in a bundle. Besides the manifest, a bundle holds the contents of the
+/// project's UUID directory: its tags file and its data directory or compressed tarball.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    uuid: Uuid,
+    name: String,
+    exported: DateTime<Local>,
+}
+
+/// Writes `project` into a zstd compressed tarball at `path`
+pub fn export(project: &Project, path: &Path) -> Result<()> {
+    let result = write_bundle(project, path);
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn write_bundle(project: &Project, path: &Path) -> Result<()> {
+    let mut project_dir = project.get_project_raw_data_path()?;
+    project_dir.pop();
+
+    let manifest = Manifest {
+        format: BUNDLE_FORMAT,
+        uuid: project.uuid,
+        name: project.name.clone(),
+        exported: Local::now(),
+    };
+    let contents = toml::ser::to_string(&manifest)?;
+
+    let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(path)?, 0)?);
+    builder.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.exported.timestamp().max(0) as u64);
+    builder.append_data(&mut header, MANIFEST_FILE_NAME, contents.as_bytes())?;
+
+    let tags_file = project_file(&project_dir);
+    builder.append_path_with_name(&tags_file, tags_file.file_name().unwrap())?;
+    let data_path = project.get_current_data_path()?;
+    let data_name = data_path.file_name().unwrap();
+    if project.compressed {
+        builder.append_path_with_name(&data_path, data_name)?;
+    } else {
+        builder.append_dir_all(data_name, &data_path)?;
+    }
+
+    builder.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Recreates the project in the bundle at `path` in the current project root. It keeps its UUID
+/// unless a project with that UUID exists already.
+pub fn import(path: &Path) -> Result<Project> {
+    let env = Environment::get()?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(File::open(path)?)?);
+    archive.set_preserve_permissions(true);
+    let mut entries = archive.entries()?;
+
+    let manifest: Manifest = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.as_os_str() != MANIFEST_FILE_NAME {
+                return Err(anyhow!(
+                    "{} is not a spiderman bundle",
+                    path.to_string_lossy()
+                ));
+            }
+            let mut contents = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut contents)?;
+            toml::de::from_str(&contents).context("Failed to parse the bundle manifest")?
+        }
+        None => return Err(anyhow!("{} is empty", path.to_string_lossy())),
+    };
+    if manifest.format > BUNDLE_FORMAT {
+        return Err(anyhow!(
+            "The bundle was written by a newer version of spiderman (format {})",
+            manifest.format
+        ));
+    }
+
+    let mut uuid = manifest.uuid;
+    if env
+        .raw_storage_dir
+        .join(uuid.hyphenated().to_string())
+        .exists()
+    {
+        uuid = Uuid::new_v4();
+        println!(
+            "A project with UUID {} exists already, importing {} as {}",
+            manifest.uuid.hyphenated(),
+            manifest.name,
+            uuid.hyphenated()
+        );
+    }
+
+    // Unpack into a staging directory, so a failure doesn't leave half a project behind
+    let staging_dir =
+        env.raw_storage_dir
+            .join(format!("{}{}", STAGING_DIR_PREFIX, uuid.hyphenated()));
+    let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+    std::fs::create_dir(&staging_dir)?;
+    let unpacked = (|| {
+        for entry in entries {
+            entry?.unpack_in(&staging_dir)?;
+        }
+        std::fs::rename(&staging_dir, &project_dir)?;
+        Project::open(&project_dir)
+    })();
+
+    match unpacked {
+        Ok(project) => Ok(project),
+        Err(e) => {
+            for dir in [&staging_dir, &project_dir] {
+                if dir.exists() {
+                    std::fs::remove_dir_all(dir)?;
+                }
+            }
+            Err(e)
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use crate::config::Config;
 use crate::schema::Schemas;
 use anyhow::{anyhow, Result};
-use once_cell::sync::OnceCell;
 use std::env::current_dir;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 const SPIDERMAN_DIR_NAME: &'static str = ".spiderman";
 const RAW_STORAGE_DIR_NAME: &'static str = "raw";
@@ -20,7 +20,9 @@ pub struct Environment {
     pub config: Config,
 }
 
-static ENVIRONMENT: OnceCell<Environment> = OnceCell::new();
+/// The environment is created on first use and only replaced by [`Environment::reload`]. Replaced
+/// environments are dropped once the last reference handed out before is gone.
+static ENVIRONMENT: RwLock<Option<Arc<Environment>>> = RwLock::new(None);
 
 impl Environment {
     fn new() -> Result<Self> {
@@ -116,7 +118,26 @@ impl Environment {
         Ok(())
     }
 
-    pub fn get() -> Result<&'static Self> {
-        ENVIRONMENT.get_or_try_init(Environment::new)
+    pub fn schema_file_path(&self) -> PathBuf {
+        self.spiderman_dir.join(SCHEMA_FILE_NAME)
+    }
+
+    pub fn get() -> Result<Arc<Self>> {
+        if let Some(env) = &*ENVIRONMENT.read().unwrap() {
+            return Ok(env.clone());
+        }
+
+        let mut env = ENVIRONMENT.write().unwrap();
+        match &*env {
+            Some(env) => Ok(env.clone()),
+            None => Ok(env.insert(Arc::new(Environment::new()?)).clone()),
+        }
+    }
+
+    /// Reads the configuration and schema again, after a command changed them
+    pub fn reload() -> Result<()> {
+        let env = Arc::new(Environment::new()?);
+        *ENVIRONMENT.write().unwrap() = Some(env);
+        Ok(())
     }
 }
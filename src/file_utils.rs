@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Result};
+use fs_extra::dir::CopyOptions;
 use std::fs::File;
 use std::io::{ErrorKind, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 
 pub fn create_or_open_file_with_dirs<B>(
@@ -26,6 +27,63 @@ where
     };
 }
 
+/// Appended to the file name of the temporary file [`write_atomically`] writes. The file is left
+/// behind if writing it fails.
+pub const TEMP_FILE_SUFFIX: &str = ".tmp";
+
+/// Replaces the contents of `path` by writing to a temporary file next to it and renaming that
+/// over the original, so readers never see a partially written file
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(TEMP_FILE_SUFFIX);
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_data()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// Moves the directory `from` to `to`, copying and removing it if the two are on different file
+/// systems
+pub fn move_directory(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(to)?;
+    let mut options = CopyOptions::new();
+    options.content_only = true;
+    fs_extra::dir::move_dir(from, to, &options)?;
+
+    Ok(())
+}
+
+/// Copies the contents of the directory `from` into the existing directory `to`. Symlinks are
+/// recreated rather than followed, so the copy has the same entries as the original.
+pub fn copy_directory(from: &Path, to: &Path) -> Result<()> {
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let source = entry.path();
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            copy_symlink(&source, &dest)?;
+        } else if file_type.is_dir() {
+            std::fs::create_dir(&dest)?;
+            copy_directory(&source, &dest)?;
+        } else {
+            std::fs::copy(&source, &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn open_in_editor(path: &Path) -> Result<bool> {
     if let Ok(editor) = std::env::var("EDITOR") {
         Command::new(editor)
@@ -43,10 +101,80 @@ pub fn open_in_editor(path: &Path) -> Result<bool> {
     }
 }
 
+/// The current directory as the shell sees it, including the symlinks it was entered through.
+///
+/// `$PWD` is only trusted if it is absolute and refers to the actual current directory, since a
+/// shell that doesn't maintain it (or a process that changed directory without updating it) would
+/// otherwise make spiderman act on the wrong directory.
 pub fn current_dir_with_symlinks() -> Result<PathBuf> {
-    if let Ok(pwd) = std::env::var("PWD") {
-        Ok(PathBuf::from(pwd))
+    let current_dir = std::env::current_dir()?;
+    if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from) {
+        if pwd.is_absolute() && pwd.canonicalize().is_ok_and(|p| p == current_dir) {
+            return Ok(pwd);
+        }
+    }
+
+    Ok(current_dir)
+}
+/// Lexically resolves `.` and `..` components without consulting the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(unix)]
+pub fn symlink_dir(source: &Path, dest: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(source, dest)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn symlink_dir(source: &Path, dest: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_dir(source, dest)?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, dest: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(source.read_link()?, dest)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn copy_symlink(source: &Path, dest: &Path) -> Result<()> {
+    let target = source.read_link()?;
+    if source.metadata().is_ok_and(|m| m.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, dest)?;
     } else {
-        Ok(std::env::current_dir()?)
+        std::os::windows::fs::symlink_file(target, dest)?;
     }
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn remove_symlink_dir(path: &Path) -> Result<()> {
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn remove_symlink_dir(path: &Path) -> Result<()> {
+    std::fs::remove_dir(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use crate::index::ProjectIndex;
+use crate::{Environment, Project};
+use anyhow::{anyhow, Result};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// A project matching a search query
+pub struct FindResult {
+    pub score: i64,
+    pub project: Project,
+}
+
+/// Fuzzy-matches `query` against the names and tag values of all projects and returns the
+/// matching projects, best match first.
+///
+/// Matches in a project's name count twice as much as matches in its tag values. A query of at
+/// least eight characters that starts the UUID of a project always selects that project.
+pub fn find_projects(query: &str) -> Result<Vec<FindResult>> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+
+    let mut results: Vec<FindResult> = Project::list()?
+        .filter_map(|project| {
+            if query.len() >= 8 && project.uuid.hyphenated().to_string().starts_with(query) {
+                return Some(FindResult {
+                    score: i64::MAX,
+                    project,
+                });
+            }
+
+            let name_score = matcher.fuzzy_match(&project.name, query).map(|s| s * 2);
+            let tag_score = project
+                .tags
+                .values()
+                .flatten()
+                .filter_map(|v| matcher.fuzzy_match(v, query))
+                .max();
+
+            name_score
+                .max(tag_score)
+                .map(|score| FindResult { score, project })
+        })
+        .collect();
+
+    results.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.project.name.cmp(&b.project.name))
+            .then_with(|| a.project.uuid.cmp(&b.project.uuid))
+    });
+
+    Ok(results)
+}
+
+/// The path to show for a project: its preferred view path, or its data path if it has no
+/// view path that can be entered.
+///
+/// The preferred view path is the one created for the first schema listing the project.
+pub fn project_path(project: &Project, data: bool) -> Result<PathBuf> {
+    let data_path = project.get_current_data_path()?;
+    if data {
+        return Ok(data_path);
+    }
+
+    // Links that were renamed because of collisions don't appear in the schema paths, so they
+    // only serve as a fallback
+    let links: Vec<_> = ProjectIndex::load()?
+        .links(&project.uuid)?
+        .into_iter()
+        .filter(|l| l.is_dir())
+        .collect();
+    let preferred = Environment::get()?
+        .schema
+        .fill(project)?
+        .into_iter()
+        .find(|p| links.contains(p));
+
+    Ok(preferred
+        .or_else(|| links.into_iter().next())
+        .unwrap_or(data_path))
+}
+
+/// Lets the user choose one of the results. The list is written to stderr, so the chosen path
+/// can be captured from stdout.
+pub fn pick(results: &[FindResult]) -> Result<Option<&FindResult>> {
+    let mut stderr = std::io::stderr();
+    for (i, result) in results.iter().enumerate() {
+        let tags = result
+            .project
+            .tags
+            .iter()
+            .map(|(tag, values)| format!("{}:{}", tag, values.join(":")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(stderr, "{:>3}  {}  [{}]", i + 1, result.project.name, tags)?;
+    }
+    write!(stderr, "Project [1]: ")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(results.first());
+    }
+
+    let choice: usize = answer
+        .parse()
+        .map_err(|_| anyhow!("{} is not a number", answer))?;
+    Ok(choice.checked_sub(1).and_then(|i| results.get(i)))
+}
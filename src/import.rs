@@ -0,0 +1,92 @@
+use crate::journal::Operation;
+use crate::project::PARENT_TAG;
+use crate::schema::{Schema, SchemaPathComponent};
+use crate::transfer::{transfer_into_root, TransferOptions};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A directory of an existing tree that will become a project
+#[derive(Debug)]
+pub struct ImportItem {
+    pub source: PathBuf,
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Finds all directories below `dir` that sit at the depth of a project in the view tree of
+/// `schema`, inferring their tags from the path components in between.
+pub fn plan_import(dir: &Path, schema: &Schema) -> Result<Vec<ImportItem>> {
+    let mut items = vec![];
+    collect_items(dir, schema.components(), &mut BTreeMap::new(), &mut items)?;
+    Ok(items)
+}
+
+fn collect_items(
+    dir: &Path,
+    components: &[SchemaPathComponent],
+    tags: &mut BTreeMap<String, String>,
+    items: &mut Vec<ImportItem>,
+) -> Result<()> {
+    let mut entries: Vec<_> = dir
+        .read_dir()?
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match components.first() {
+            None => items.push(ImportItem {
+                source: entry.path(),
+                tags: tags.clone(),
+            }),
+            Some(SchemaPathComponent::Fixed(fixed)) => {
+                if *fixed == name {
+                    collect_items(&entry.path(), &components[1..], tags, items)?;
+                }
+            }
+            Some(SchemaPathComponent::Tag(tag)) => {
+                tags.insert(tag.clone(), name);
+                collect_items(&entry.path(), &components[1..], tags, items)?;
+                tags.remove(tag);
+            }
+            // The creation date is set when the project is created, not taken from the tree
+            Some(SchemaPathComponent::Created(_)) => {
+                collect_items(&entry.path(), &components[1..], tags, items)?;
+            }
+            Some(SchemaPathComponent::Parent) => {
+                tags.insert(PARENT_TAG.to_string(), name);
+                collect_items(&entry.path(), &components[1..], tags, items)?;
+                tags.remove(PARENT_TAG);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves all planned directories into the project root. Failures are reported and skipped, the
+/// returned operations describe the projects that were imported.
+pub fn run_import(items: &[ImportItem], options: TransferOptions) -> Vec<Operation> {
+    let mut operations = vec![];
+
+    for item in items {
+        match transfer_into_root(&item.source, &item.tags, options) {
+            Ok(project) => operations.push(Operation::Move {
+                uuid: project.uuid,
+                source: item.source.clone(),
+                copy: options.copy,
+                link_back: options.link_back,
+            }),
+            Err(e) => eprintln!(
+                "WARNING: Failed to import {}: {}",
+                item.source.to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    operations
+}
@@ -0,0 +1,246 @@
+use crate::file_utils::write_atomically;
+use crate::weave::ViewLink;
+use crate::{Environment, Project};
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const INDEX_FILE_NAME: &str = "index.toml";
+
+/// Cache of all projects in the project root, stored in the spiderman directory.
+///
+/// An entry is only trusted as long as the modification times of the project's UUID directory
+/// and tags file match the recorded ones, so edits made outside of spiderman are picked up on
+/// the next read.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProjectIndex {
+    /// Modification time of `schema.toml` in nanoseconds since the Unix epoch, the tag registry
+    /// affects how tags are read
+    #[serde(default)]
+    schema_modified: u64,
+    #[serde(default)]
+    projects: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Modification time of the UUID directory in nanoseconds since the Unix epoch
+    dir_modified: u64,
+    /// Modification time of the tags file in nanoseconds since the Unix epoch
+    tags_modified: u64,
+    /// When a weave first linked the project, in nanoseconds since the Unix epoch, 0 if it never
+    /// has been
+    #[serde(default)]
+    pub first_woven: u64,
+    /// View paths the project was linked to by the last weave, relative to the project root
+    #[serde(default)]
+    pub links: Vec<PathBuf>,
+    pub project: Project,
+}
+
+impl ProjectIndex {
+    fn path() -> Result<PathBuf> {
+        Ok(Environment::get()?.spiderman_dir.join(INDEX_FILE_NAME))
+    }
+
+    /// Loads the index, starting over with an empty one if it is missing or unreadable
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        match toml::de::from_slice(&std::fs::read(&path)?) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                eprintln!(
+                    "WARNING: Rebuilding corrupt project index {}: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write_atomically(&Self::path()?, &toml::ser::to_vec(self)?)
+    }
+
+    /// Brings the index up to date with the raw data directory and returns all projects, ordered
+    /// by UUID. Only projects that changed since the last refresh are read from disk.
+    pub fn refresh(&mut self) -> Result<Vec<Project>> {
+        let env = Environment::get()?;
+        let schema_modified = modification_time(&env.schema_file_path());
+        let cached: HashMap<Uuid, IndexEntry> = self
+            .projects
+            .drain(..)
+            .map(|e| (e.project.uuid, e))
+            .collect();
+        let schema_changed = schema_modified != self.schema_modified;
+        self.schema_modified = schema_modified;
+
+        // Checking and reading the project directories is dominated by I/O latency on network
+        // file systems, so all projects are handled in parallel
+        let directories: Vec<_> = env
+            .raw_storage_dir
+            .read_dir()?
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+            .collect();
+        let refreshed: Vec<(IndexEntry, bool)> = directories
+            .par_iter()
+            .filter_map(|dir| {
+                let path = dir.path();
+                let (dir_modified, tags_modified) = Self::modification_times(&path);
+                let cached_entry = Uuid::parse_str(&dir.file_name().to_string_lossy())
+                    .ok()
+                    .and_then(|uuid| cached.get(&uuid));
+
+                if let Some(entry) = cached_entry {
+                    if !schema_changed
+                        && entry.dir_modified == dir_modified
+                        && entry.tags_modified == tags_modified
+                    {
+                        return Some((entry.clone(), false));
+                    }
+                }
+
+                match Project::read(&path) {
+                    Ok(project) => Some((
+                        IndexEntry {
+                            dir_modified,
+                            tags_modified,
+                            first_woven: cached_entry.map_or(0, |e| e.first_woven),
+                            // The links stay valid until the next weave records new ones
+                            links: cached_entry.map(|e| e.links.clone()).unwrap_or_default(),
+                            project,
+                        },
+                        true,
+                    )),
+                    Err(e) => {
+                        eprintln!(
+                            "WARNING: Ignoring project at {}: {}",
+                            path.to_string_lossy(),
+                            e
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        // Projects that were removed from the raw data directory also change the index
+        let changed =
+            schema_changed || refreshed.len() != cached.len() || refreshed.iter().any(|(_, c)| *c);
+
+        self.projects = refreshed.into_iter().map(|(e, _)| e).collect();
+        self.projects.sort_by_key(|e| e.project.uuid);
+        if changed {
+            self.save()?;
+        }
+
+        let mut projects: Vec<Project> = self.projects.iter().map(|e| e.project.clone()).collect();
+        Project::resolve_inheritance(&mut projects);
+        Ok(projects)
+    }
+
+    fn modification_times(project_dir: &Path) -> (u64, u64) {
+        let tags_file = Project::find_tags_file(project_dir);
+        (
+            modification_time(project_dir),
+            modification_time(&tags_file),
+        )
+    }
+
+    /// Records the links created by a weave
+    pub fn record_links(&mut self, links: &[ViewLink]) -> Result<()> {
+        let base_path = &Environment::get()?.base_path;
+        let mut links_by_project: HashMap<Uuid, Vec<PathBuf>> = HashMap::new();
+        for link in links {
+            links_by_project
+                .entry(link.uuid)
+                .or_default()
+                .push(link.link.strip_prefix(base_path)?.to_path_buf());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        for entry in &mut self.projects {
+            entry.links = links_by_project
+                .remove(&entry.project.uuid)
+                .unwrap_or_default();
+            if entry.first_woven == 0 && !entry.links.is_empty() {
+                entry.first_woven = now;
+            }
+        }
+
+        self.save()
+    }
+
+    /// UUIDs of the projects matching `name_or_uuid` as in [`Project::lookup`]
+    pub fn find(&self, name_or_uuid: &str) -> Vec<Uuid> {
+        self.projects
+            .iter()
+            .filter(|e| e.project.matches(name_or_uuid))
+            .map(|e| e.project.uuid)
+            .collect()
+    }
+
+    pub fn entry(&self, uuid: &Uuid) -> Option<&IndexEntry> {
+        self.projects
+            .binary_search_by_key(uuid, |e| e.project.uuid)
+            .ok()
+            .map(|i| &self.projects[i])
+    }
+
+    /// Absolute paths of the links the last weave created for the project `uuid`
+    pub fn links(&self, uuid: &Uuid) -> Result<Vec<PathBuf>> {
+        let base_path = &Environment::get()?.base_path;
+        Ok(self
+            .projects
+            .iter()
+            .filter(|e| e.project.uuid == *uuid)
+            .flat_map(|e| e.links.iter().map(|l| base_path.join(l)))
+            .collect())
+    }
+
+    /// Returns the UUID of the project whose woven link `path` is or lies below
+    pub fn project_at(&self, path: &Path) -> Result<Option<Uuid>> {
+        let base_path = &Environment::get()?.base_path;
+        let relative_path = match path.strip_prefix(base_path) {
+            Ok(p) => p,
+            Err(_) => return Ok(None),
+        };
+
+        // Like the uncached lookup, prefer the outermost link if links are nested
+        let mut project: Option<(usize, Uuid)> = None;
+        for entry in &self.projects {
+            for link in &entry.links {
+                let depth = link.components().count();
+                if relative_path.starts_with(link)
+                    && project.is_none_or(|(d, _)| depth < d)
+                    && base_path.join(link).is_symlink()
+                {
+                    project = Some((depth, entry.project.uuid));
+                }
+            }
+        }
+
+        Ok(project.map(|(_, uuid)| uuid))
+    }
+}
+
+/// Modification time of `path` in nanoseconds since the Unix epoch, 0 if it is unknown
+fn modification_time(path: &Path) -> u64 {
+    path.metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos() as u64)
+}
@@ -0,0 +1,176 @@
+use crate::backend::LinkBackendKind;
+use crate::index::ProjectIndex;
+use crate::metadata::ProjectState;
+use crate::relations::relations_of;
+use crate::{Environment, Project};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prints everything spiderman knows about `project`
+pub fn print_info(project: &Project) -> Result<()> {
+    let env = Environment::get()?;
+    let data_path = project.get_current_data_path()?;
+
+    println!("UUID:      {}", project.uuid.hyphenated());
+    println!("Name:      {}", project.name);
+    println!("Data:      {}", data_path.to_string_lossy());
+    if project.metadata.state == ProjectState::Archived {
+        if project.compressed {
+            println!("State:     archived, compressed");
+        } else {
+            println!("State:     archived");
+        }
+    }
+
+    if let Some(parent) = &project.parent_name {
+        println!("Parent:    {}", parent);
+    }
+
+    println!("Tags:");
+    for (tag, values) in &project.own_tags {
+        println!("  {}: {}", tag, values.join(", "));
+    }
+    let inherited = project.inherited_tags();
+    if !inherited.is_empty() {
+        println!("Inherited tags:");
+        for (tag, values) in &inherited {
+            println!("  {}: {}", tag, values.join(", "));
+        }
+    }
+
+    let backend = env.schema.link_backend.backend(env.schema.link_style);
+    let links = ProjectIndex::load()?.links(&project.uuid)?;
+    let view_paths = env.schema.fill(project)?;
+    println!("View paths:");
+    for path in &view_paths {
+        let entry_exists =
+            env.schema.link_backend == LinkBackendKind::Index || backend.entry_path(path).exists();
+        let status = if links.contains(path) && entry_exists {
+            "linked"
+        } else if entry_exists {
+            "taken by another project"
+        } else {
+            "missing, run spiderman weave"
+        };
+        println!("  {}  ({})", relative_to_root(path, &env.base_path), status);
+    }
+
+    // Links that had to be renamed because of collisions
+    let relations_view = env
+        .schema
+        .relations_view
+        .as_ref()
+        .map(|v| env.base_path.join(v));
+    for link in links.iter().filter(|l| {
+        !view_paths.contains(l) && !relations_view.as_ref().is_some_and(|v| l.starts_with(v))
+    }) {
+        println!("  {}  (linked)", relative_to_root(link, &env.base_path));
+    }
+
+    let projects: Vec<Project> = Project::list()?.collect();
+    let relations = relations_of(project, &projects);
+    if !relations.is_empty() {
+        println!("Relations:");
+        for (kind, other) in relations {
+            println!(
+                "  {} {} ({})",
+                kind,
+                other.name,
+                &other.uuid.hyphenated().to_string()[..8]
+            );
+        }
+    }
+
+    let stats = DataStats::collect(&data_path)?;
+    println!("Size:      {}", format_size(stats.bytes));
+    if let Some(modified) = stats.modified {
+        let modified: DateTime<Local> = modified.into();
+        println!("Modified:  {}", modified.format("%Y-%m-%d %H:%M:%S"));
+    }
+
+    if let Some(status) = git_status(&data_path) {
+        println!("Git:");
+        for line in status.lines() {
+            println!("  {}", line);
+        }
+    }
+
+    Ok(())
+}
+
+fn relative_to_root(path: &Path, base_path: &Path) -> String {
+    path.strip_prefix(base_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Total size and most recent modification of everything in a directory, not following symlinks
+#[derive(Debug, Default)]
+struct DataStats {
+    bytes: u64,
+    modified: Option<SystemTime>,
+}
+
+impl DataStats {
+    fn collect(dir: &Path) -> Result<Self> {
+        let mut stats = Self::default();
+        stats.add(dir)?;
+        Ok(stats)
+    }
+
+    fn add(&mut self, path: &Path) -> Result<()> {
+        let metadata = path.symlink_metadata()?;
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        self.modified = self.modified.max(Some(modified));
+
+        if metadata.is_dir() {
+            for entry in path.read_dir()? {
+                self.add(&entry?.path())?;
+            }
+        } else {
+            self.bytes += metadata.len();
+        }
+
+        Ok(())
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Short status of the git repository in `dir`, if it is one and git is installed
+fn git_status(dir: &Path) -> Option<String> {
+    if !dir.join(".git").exists() {
+        return None;
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--short", "--branch"])
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
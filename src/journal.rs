@@ -0,0 +1,283 @@
+use crate::archive;
+use crate::backup::restore_project_file;
+use crate::file_utils::{move_directory, remove_symlink_dir, write_atomically};
+use crate::metadata::{METADATA_FILE_NAME, TAGS_FILE_NAME};
+use crate::transfer::DirStats;
+use crate::{Environment, Project};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const JOURNAL_FILE_NAME: &str = "journal.toml";
+const MAX_JOURNAL_ENTRIES: usize = 1000;
+
+/// A change to several files that is computed up front, previewed and confirmed before anything
+/// is written, and then recorded in the journal
+pub trait Plan {
+    fn is_empty(&self) -> bool;
+
+    /// Lists the files or projects the plan will change
+    fn print_preview(&self);
+
+    /// Question asked before applying the plan
+    fn confirmation(&self) -> String;
+
+    /// Description of the journal entry
+    fn description(&self) -> String;
+
+    /// Writes the changes one after another, pushing the operations that reverse each of them
+    fn apply_each(&self, operations: &mut Vec<Operation>) -> Result<()>;
+
+    /// Writes all changes and returns the operations that reverse them. Operations are returned
+    /// along with the error if only some of the files could be written.
+    fn apply(&self) -> (Vec<Operation>, Result<()>) {
+        let mut operations = vec![];
+        let result = self.apply_each(&mut operations);
+        (operations, result)
+    }
+}
+
+/// Record of the mutating commands run in a project root, with enough data to reverse them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    #[serde(default)]
+    entries: Vec<JournalEntry>,
+}
+
+/// A single command, which may consist of several operations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: DateTime<Local>,
+    pub description: String,
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Operation {
+    /// A new, empty project was created
+    New { uuid: Uuid },
+    /// An existing directory was moved or copied into the project root as a new project
+    Move {
+        uuid: Uuid,
+        source: PathBuf,
+        #[serde(default)]
+        copy: bool,
+        #[serde(default)]
+        link_back: bool,
+    },
+    /// The tags file of a project was changed, `previous` holds its former contents
+    EditTags { uuid: Uuid, previous: String },
+    /// `schema.toml` was changed, `previous` holds its former contents
+    EditSchema { previous: String },
+    /// The `spiderman.tags` file of a project was replaced by `spiderman.toml`, `previous` holds
+    /// its contents
+    ConvertTagsFile { uuid: Uuid, previous: String },
+    /// The tags file of a project was restored from a snapshot. `file_name` and `previous` hold
+    /// the name and contents of the former file, if there was one.
+    RestoreProjectFile {
+        uuid: Uuid,
+        file_name: Option<String>,
+        previous: String,
+    },
+    /// A project was created from the bundle at `bundle`, its data looked like `data` then
+    ImportBundle {
+        uuid: Uuid,
+        bundle: PathBuf,
+        #[serde(default)]
+        data: Option<DirStats>,
+    },
+    /// The data directory of a project was replaced by a compressed tarball
+    Compress { uuid: Uuid },
+    /// The compressed tarball of a project was replaced by its data directory
+    Extract { uuid: Uuid },
+}
+
+impl Journal {
+    fn path() -> Result<PathBuf> {
+        Ok(Environment::get()?.spiderman_dir.join(JOURNAL_FILE_NAME))
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        toml::de::from_slice(&std::fs::read(&path)?)
+            .with_context(|| format!("Failed to parse journal {}", path.to_string_lossy()))
+    }
+
+    fn save(&self) -> Result<()> {
+        write_atomically(&Self::path()?, &toml::ser::to_vec(self)?)
+    }
+
+    /// Appends an entry for a command that has just been completed
+    pub fn record(description: impl Into<String>, operations: Vec<Operation>) -> Result<()> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        let mut journal = Self::load()?;
+        journal.entries.push(JournalEntry {
+            timestamp: Local::now(),
+            description: description.into(),
+            operations,
+        });
+
+        if journal.entries.len() > MAX_JOURNAL_ENTRIES {
+            let excess = journal.entries.len() - MAX_JOURNAL_ENTRIES;
+            journal.entries.drain(..excess);
+        }
+
+        journal.save()
+    }
+
+    /// Entries from the most recent to the oldest one
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter().rev()
+    }
+
+    /// Reverses the `count` most recent entries, most recent first. Entries are removed from
+    /// the journal as soon as they have been undone, so a failure leaves the journal consistent.
+    pub fn undo(count: usize) -> Result<Vec<JournalEntry>> {
+        let mut journal = Self::load()?;
+        let mut undone = vec![];
+
+        for _ in 0..count {
+            let entry = match journal.entries.last() {
+                Some(e) => e.clone(),
+                None => break,
+            };
+
+            for operation in entry.operations.iter().rev() {
+                operation
+                    .undo()
+                    .with_context(|| format!("Failed to undo \"{}\"", entry.description))?;
+            }
+
+            journal.entries.pop();
+            journal.save()?;
+            undone.push(entry);
+        }
+
+        Ok(undone)
+    }
+}
+
+impl Operation {
+    fn undo(&self) -> Result<()> {
+        let env = Environment::get()?;
+
+        match self {
+            Operation::New { uuid } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                let project = Project::open(&project_dir)?;
+                let data_path = project.get_project_raw_data_path()?;
+                if data_path.read_dir()?.next().is_some() {
+                    return Err(anyhow!(
+                        "Project {} is not empty anymore, remove {} manually",
+                        project.name,
+                        project_dir.to_string_lossy()
+                    ));
+                }
+
+                std::fs::remove_dir_all(project_dir)?;
+            }
+            Operation::Move {
+                uuid,
+                source,
+                copy,
+                link_back,
+            } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                let project = Project::open(&project_dir)?;
+                if *link_back && source.is_symlink() {
+                    remove_symlink_dir(source)?;
+                }
+
+                // A copied project can simply be dropped, as long as the original is still there
+                if !(*copy && source.exists()) {
+                    if source.exists() {
+                        return Err(anyhow!(
+                            "Can't move project {} back, {} exists",
+                            project.name,
+                            source.to_string_lossy()
+                        ));
+                    }
+
+                    move_directory(&project.get_project_raw_data_path()?, source)?;
+                }
+                std::fs::remove_dir_all(project_dir)?;
+            }
+            Operation::EditTags { uuid, previous } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                let project = Project::open(&project_dir)?;
+                write_atomically(&project.get_tags_file_path()?, previous.as_bytes())?;
+            }
+            Operation::ConvertTagsFile { uuid, previous } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                write_atomically(&project_dir.join(TAGS_FILE_NAME), previous.as_bytes())?;
+                std::fs::remove_file(project_dir.join(METADATA_FILE_NAME))?;
+            }
+            Operation::RestoreProjectFile {
+                uuid,
+                file_name,
+                previous,
+            } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                match file_name {
+                    Some(file_name) => restore_project_file(&project_dir, file_name, previous)?,
+                    None => {
+                        for name in [TAGS_FILE_NAME, METADATA_FILE_NAME] {
+                            let path = project_dir.join(name);
+                            if path.exists() {
+                                std::fs::remove_file(path)?;
+                            }
+                        }
+                    }
+                }
+            }
+            Operation::ImportBundle { uuid, bundle, data } => {
+                // The project can be dropped, as long as it can be imported again
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                if !bundle.is_file() {
+                    return Err(anyhow!(
+                        "Bundle {} is gone, remove {} manually",
+                        bundle.to_string_lossy(),
+                        project_dir.to_string_lossy()
+                    ));
+                }
+                if let Some(data) = data {
+                    let project = Project::open(&project_dir)?;
+                    let data_path = project.get_current_data_path()?;
+                    if DirStats::collect(&data_path).ok().as_ref() != Some(data) {
+                        return Err(anyhow!(
+                            "Project {} changed since it was imported, remove {} manually",
+                            project.name,
+                            project_dir.to_string_lossy()
+                        ));
+                    }
+                }
+
+                std::fs::remove_dir_all(project_dir)?;
+            }
+            Operation::Compress { uuid } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                archive::extract_data(&Project::read(&project_dir)?)?;
+            }
+            Operation::Extract { uuid } => {
+                let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+                archive::compress_data(&Project::read(&project_dir)?)?;
+            }
+            Operation::EditSchema { previous } => {
+                write_atomically(&env.schema_file_path(), previous.as_bytes())?;
+                Environment::reload()?;
+            }
+        }
+
+        Ok(())
+    }
+}
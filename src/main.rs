@@ -1,21 +1,40 @@
 #![feature(absolute_path)]
 #![feature(is_some_and)]
 
+mod archive;
+mod backend;
+mod backup;
+mod bundle;
 mod config;
 mod environment;
 mod file_utils;
+mod find;
+mod import;
+mod index;
+mod info;
+mod journal;
+mod metadata;
+#[cfg(feature = "fuse")]
+mod mount;
 mod project;
+mod registry;
+mod relations;
+mod retag;
 mod schema;
+mod shell;
+mod transfer;
+mod vocabulary;
 mod weave;
 
+use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
 
 use crate::environment::Environment;
-use crate::file_utils::open_in_editor;
+use crate::journal::{Journal, Operation, Plan};
 use crate::project::Project;
+use crate::transfer::{transfer_into_root, DirStats, TransferOptions};
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
-use fs_extra::dir::{move_dir, CopyOptions};
+use clap::{CommandFactory, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(author, version)]
@@ -41,7 +60,10 @@ use fs_extra::dir::{move_dir, CopyOptions};
 ///
 /// A project's tags are specified in its `spiderman.tags` file, which can be edited using the **tags** subcommand.
 /// Each line in this file consists of colon (`:`) separated values. The first of these is the name of the tag, while the
-/// later ones are values for that tag. A project may have multiple values for one tag.
+/// later ones are values for that tag. A project may have multiple values for one tag. Files starting with
+/// `# spiderman tags v2` may contain `#` comments, and values containing `:` or `#` can be put in double quotes or
+/// escaped with a backslash. Instead of `spiderman.tags`, a project may have a `spiderman.toml` file, which can also
+/// hold a description and creation date; **migrate** converts existing files.
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -65,9 +87,200 @@ enum Commands {
     Move {
         /// Path to the project to be moved, must be a directory
         source: PathBuf,
+        /// Copy the project and keep the original directory
+        #[arg(long, conflicts_with = "link_back")]
+        copy: bool,
+        /// Leave a symlink to the moved project where it used to be
+        #[arg(long)]
+        link_back: bool,
+    },
+    /// Moves all projects of an existing directory tree into the current spiderman project root,
+    /// inferring their tags from the directories they are in
+    ///
+    /// The tree has to be laid out like the view tree of the chosen schema: every directory at the
+    /// depth of the schema becomes a project, and the directories above it provide the values of
+    /// the tags in the corresponding schema components.
+    Import {
+        /// Root of the directory tree to import
+        dir: PathBuf,
+        /// Number of the schema in `schema.toml` the tree is organised by, starting at 1
+        #[arg(long)]
+        schema: usize,
+        /// Import without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+        /// Copy the projects and keep the original tree
+        #[arg(long)]
+        copy: bool,
+    },
+    /// Writes a project with its tags and metadata into a single zstd compressed tarball, which
+    /// **import-bundle** turns into a project again
+    Export {
+        /// Name or UUID of the project, defaults to the current project
+        project: Option<String>,
+        /// Path of the bundle, defaults to the project name with `.tar.zst` appended
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Recreates a project written by **export** in the current project root
+    ///
+    /// The project keeps its UUID, unless the project root contains a project with that UUID
+    /// already.
+    ImportBundle {
+        /// Path of the bundle
+        bundle: PathBuf,
+    },
+    /// Writes a snapshot of `schema.toml` and the tags and metadata of all projects into
+    /// `.spiderman/backups`
+    ///
+    /// Only metadata is included, not the data of the projects.
+    Backup,
+    /// Restores `schema.toml` and the tags and metadata of all projects from a snapshot written
+    /// by **backup**
+    RestoreMetadata {
+        /// Path of the snapshot or its file name in `.spiderman/backups`
+        snapshot: PathBuf,
+        /// Restore without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Prints the path of the project whose name or tag values best match a search term
+    ///
+    /// The term is matched fuzzily, so `spiderman find thes` finds a project called `thesis`.
+    /// The preferred view path of the project is printed, which is the one created for the first
+    /// schema listing it. Together with the shell integration, `spiderman cd` changes into it.
+    #[command(visible_alias = "cd")]
+    Find {
+        /// Search term matched against project names and tag values
+        query: String,
+        /// Choose from all matching projects, best match first
+        #[arg(short, long)]
+        interactive: bool,
+        /// Print the path of the project data instead of a view path
+        #[arg(long)]
+        data: bool,
+    },
+    /// Shows the tags, view paths, size and version control status of a project
+    Info {
+        /// Name or UUID of the project, defaults to the current project
+        project: Option<String>,
+    },
+    /// Hides a project from the normal schemas, it is only linked by schemas with `archive = true`
+    ///
+    /// Without `--compress` or `--extract`, the data of the project is left as it is.
+    Archive {
+        /// Name or UUID of the project, defaults to the current project
+        project: Option<String>,
+        /// Replace the data directory by a zstd compressed tarball
+        #[arg(long)]
+        compress: bool,
+        /// Extract the data directory of a compressed project again
+        #[arg(long, conflicts_with = "compress")]
+        extract: bool,
+    },
+    /// Returns an archived project to the normal schemas, extracting its data if it is compressed
+    Unarchive {
+        /// Name or UUID of the project, defaults to the current project
+        project: Option<String>,
+    },
+    /// Records a relation of the project `from` to the project `to`
+    ///
+    /// Relations are stored in the `spiderman.toml` file of `from`. If `relations_view` is set in
+    /// `schema.toml`, that directory of the view tree lists the related projects of every project,
+    /// including the projects relating to it.
+    Link {
+        /// Name or UUID of the project the relation is recorded for
+        from: String,
+        /// Name or UUID of the related project
+        to: String,
+        #[arg(long, value_enum, default_value = "related")]
+        kind: relations::RelationKind,
+        /// Remove the relation instead
+        #[arg(long)]
+        remove: bool,
     },
     /// Edit tags of the current project (the current working directory must be a project directory)
-    Tags,
+    Tags {
+        #[command(subcommand)]
+        command: Option<TagsCommand>,
+    },
+    /// Converts the tags files of all projects to the current format
+    Migrate {
+        /// Convert them to `spiderman.toml` files instead, which also record the creation date
+        #[arg(long)]
+        toml: bool,
+    },
+    /// Lists the most recent mutating commands, which can be reversed with **undo**
+    History,
+    /// Reverses the most recent mutating commands
+    Undo {
+        /// Number of commands to undo
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// Prints shell functions integrating spiderman into the shell
+    ///
+    /// Load them with `eval "$(spiderman shell-init bash)"` in `~/.bashrc`, `eval "$(spiderman shell-init zsh)"`
+    /// in `~/.zshrc` or `spiderman shell-init fish | source` in `~/.config/fish/config.fish`. They define
+    /// **spcd**, which changes into the directory printed by **find** (also available as `spiderman cd`), follow
+    /// the current directory when it is moved into the project root, and complete project names, UUIDs, tag
+    /// names and tag values.
+    ShellInit { shell: shell::Shell },
+    /// Prints where the shell should continue after the directory `old_pwd` was moved by the last command
+    #[command(name = "__relocate", hide = true)]
+    Relocate { old_pwd: PathBuf },
+    /// Prints completion candidates, one per line
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(required = true)]
+        kinds: Vec<shell::CompletionKind>,
+    },
+    /// Serves the view tree as a read-only virtual filesystem instead of weaving symlinks
+    #[cfg(feature = "fuse")]
+    Mount {
+        /// Directory to mount the view tree on
+        mountpoint: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagsCommand {
+    /// Lists all tags and values with the number of projects using them, and shows where tags and
+    /// schemas don't fit together
+    List,
+    /// Rewrites the tags files of all projects with the aliases declared in `schema.toml` replaced
+    Normalize,
+    /// Renames a tag in all projects, `default_tag_values`, the tag registry and schema placeholders
+    RenameKey {
+        old: String,
+        new: String,
+        /// Apply the changes without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Renames a value of a tag in all projects, `default_tag_values` and the tag registry
+    RenameValue {
+        tag: String,
+        old: String,
+        new: String,
+        /// Apply the changes without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Replaces several values of a tag by a single one in all projects, `default_tag_values` and
+    /// the tag registry
+    MergeValues {
+        tag: String,
+        /// Values to be replaced
+        #[arg(required = true)]
+        values: Vec<String>,
+        /// Value replacing them
+        #[arg(long)]
+        into: String,
+        /// Apply the changes without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -83,21 +296,172 @@ fn main() -> Result<()> {
         Commands::New { name } => {
             new(name).context("Failed to create project")?;
         }
-        Commands::Move { source } => {
-            move_project(source).context("Failed to move project")?;
+        Commands::Move {
+            source,
+            copy,
+            link_back,
+        } => {
+            let options = TransferOptions {
+                copy: *copy,
+                link_back: *link_back,
+            };
+            move_project(source, options).context("Failed to move project")?;
+        }
+        Commands::Import {
+            dir,
+            schema,
+            yes,
+            copy,
+        } => {
+            let options = TransferOptions {
+                copy: *copy,
+                link_back: false,
+            };
+            import(dir, *schema, *yes, options).context("Failed to import projects")?;
+        }
+        Commands::Export { project, output } => {
+            export(project, output).context("Failed to export project")?;
+        }
+        Commands::ImportBundle { bundle } => {
+            import_bundle(bundle).context("Failed to import bundle")?;
+        }
+        Commands::Backup => {
+            let path = backup::backup().context("Failed to write backup")?;
+            println!("Wrote {}", path.to_string_lossy());
+        }
+        Commands::RestoreMetadata { snapshot, yes } => {
+            restore_metadata(snapshot, *yes).context("Failed to restore metadata")?;
+        }
+        Commands::Find {
+            query,
+            interactive,
+            data,
+        } => {
+            find(query, *interactive, *data).context("Failed to find project")?;
+        }
+        Commands::Info { project } => {
+            info(project).context("Failed to show project info")?;
         }
-        Commands::Tags => {
+        Commands::Tags { command: None } => {
             tags().context("Failed to edit tags")?;
         }
+        Commands::Tags {
+            command: Some(TagsCommand::List),
+        } => {
+            vocabulary::Vocabulary::collect()
+                .context("Failed to list tags")?
+                .print();
+        }
+        Commands::Tags {
+            command: Some(TagsCommand::Normalize),
+        } => {
+            normalize_tags().context("Failed to normalize tags")?;
+        }
+        Commands::Tags {
+            command: Some(TagsCommand::RenameKey { old, new, yes }),
+        } => {
+            let change = retag::TagChange::RenameKey {
+                old: old.clone(),
+                new: new.clone(),
+            };
+            retag(change, *yes).context("Failed to rename tag")?;
+        }
+        Commands::Tags {
+            command: Some(TagsCommand::RenameValue { tag, old, new, yes }),
+        } => {
+            let change = retag::TagChange::ReplaceValues {
+                tag: tag.clone(),
+                values: vec![old.clone()],
+                into: new.clone(),
+            };
+            retag(change, *yes).context("Failed to rename tag value")?;
+        }
+        Commands::Tags {
+            command:
+                Some(TagsCommand::MergeValues {
+                    tag,
+                    values,
+                    into,
+                    yes,
+                }),
+        } => {
+            let change = retag::TagChange::ReplaceValues {
+                tag: tag.clone(),
+                values: values.clone(),
+                into: into.clone(),
+            };
+            retag(change, *yes).context("Failed to merge tag values")?;
+        }
+        Commands::Archive {
+            project,
+            compress,
+            extract,
+        } => {
+            let compression = (*compress || *extract).then_some(*compress);
+            archive(project, compression).context("Failed to archive project")?;
+        }
+        Commands::Unarchive { project } => {
+            unarchive(project).context("Failed to unarchive project")?;
+        }
+        Commands::Link {
+            from,
+            to,
+            kind,
+            remove,
+        } => {
+            link(from, to, *kind, *remove).context("Failed to link projects")?;
+        }
+        Commands::Migrate { toml } => {
+            migrate(*toml).context("Failed to migrate tags files")?;
+        }
+        Commands::History => {
+            history().context("Failed to show history")?;
+        }
+        Commands::Undo { count } => {
+            undo(*count).context("Failed to undo")?;
+        }
+        Commands::ShellInit { shell } => {
+            print!("{}", shell::init_script(*shell));
+        }
+        Commands::Relocate { old_pwd } => {
+            if let Some(dir) = shell::relocated_dir(old_pwd)? {
+                println!("{}", dir.to_string_lossy());
+            }
+        }
+        Commands::Complete { kinds } => {
+            let cli = Cli::command();
+            let subcommands = |command: &clap::Command| -> Vec<String> {
+                command
+                    .get_subcommands()
+                    .filter(|c| !c.is_hide_set())
+                    .flat_map(|c| std::iter::once(c.get_name()).chain(c.get_visible_aliases()))
+                    .map(|c| c.to_owned())
+                    .collect()
+            };
+            let tags_commands = cli
+                .find_subcommand("tags")
+                .map(subcommands)
+                .unwrap_or_default();
+            for candidate in shell::completions(kinds, &subcommands(&cli), &tags_commands)? {
+                println!("{}", candidate);
+            }
+        }
+        #[cfg(feature = "fuse")]
+        Commands::Mount { mountpoint } => {
+            mount::mount(mountpoint).context("Failed to mount view tree")?;
+        }
     }
 
     Ok(())
 }
 
 fn weave() -> Result<()> {
+    // Plan before removing anything, so a weave that can't succeed leaves the view tree intact
+    let plan = weave::ViewPlan::new().context("Failed to plan view tree")?;
+    plan.warn_missing_relations();
     weave::remove_symlinks().expect("Failed to remove symlinks in view tree");
     weave::remove_empty_directories().expect("Failed to remove empty directories in view tree");
-    weave::construct_view_tree().expect("Failed to construct view tree");
+    weave::construct_view_tree(&plan).expect("Failed to construct view tree");
 
     Ok(())
 }
@@ -118,31 +482,209 @@ fn init(dir: &Option<PathBuf>) -> Result<()> {
 }
 
 fn new(name: &str) -> Result<()> {
-    Project::new(name)?;
+    let project = Project::new(name)?;
+    Journal::record(
+        format!("new {}", name),
+        vec![Operation::New { uuid: project.uuid }],
+    )?;
 
     weave()?;
     Ok(())
 }
 
-fn move_project(source: &Path) -> Result<()> {
-    if source.exists() && source.is_dir() {
-        let project_name = std::path::absolute(source)?
-            .file_name()
-            .ok_or(anyhow!("Source directory has no name"))?
-            .to_string_lossy()
-            .into_owned();
-        let project = Project::new(project_name.as_ref())?;
-        let new_path = project.get_project_raw_data_path()?;
-        let options = CopyOptions::new();
-        move_dir(source, new_path, &options)?;
-        weave()?;
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "{} does not exist or is not a directory",
+fn move_project(source: &Path, options: TransferOptions) -> Result<()> {
+    let project = transfer_into_root(source, &Project::tags_from_current_dir()?, options)?;
+    Journal::record(
+        format!(
+            "{} {}",
+            if options.copy { "copy" } else { "move" },
             source.to_string_lossy()
-        ))
+        ),
+        vec![Operation::Move {
+            uuid: project.uuid,
+            source: std::path::absolute(source)?,
+            copy: options.copy,
+            link_back: options.link_back,
+        }],
+    )?;
+
+    Project::edit_tags_file(&project.get_tags_file_path()?)?;
+    weave()?;
+    Ok(())
+}
+
+fn import(dir: &Path, schema: usize, yes: bool, options: TransferOptions) -> Result<()> {
+    let schemas = &Environment::get()?.schema.schemas;
+    let schema = schema
+        .checked_sub(1)
+        .and_then(|i| schemas.get(i))
+        .ok_or(anyhow!(
+            "There is no schema {}, schema.toml contains {} schemas",
+            schema,
+            schemas.len()
+        ))?;
+
+    let items = import::plan_import(dir, schema)?;
+    if items.is_empty() {
+        println!("Nothing to import in {}", dir.to_string_lossy());
+        return Ok(());
+    }
+
+    for item in &items {
+        let tags = item
+            .tags
+            .iter()
+            .map(|(tag, value)| format!("{}:{}", tag, value))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("{}  [{}]", item.source.to_string_lossy(), tags);
+    }
+
+    if !yes && !confirm(&format!("Import {} projects?", items.len()))? {
+        return Ok(());
+    }
+
+    let operations = import::run_import(&items, options);
+    println!("Imported {} of {} projects", operations.len(), items.len());
+    Journal::record(format!("import {}", dir.to_string_lossy()), operations)?;
+
+    weave()?;
+    Ok(())
+}
+
+fn export(project: &Option<String>, output: &Option<PathBuf>) -> Result<()> {
+    let project = project_or_current(project)?;
+    let output = output
+        .clone()
+        .unwrap_or(PathBuf::from(format!("{}.tar.zst", project.name)));
+
+    bundle::export(&project, &output)?;
+    println!("Exported {} to {}", project.name, output.to_string_lossy());
+    Ok(())
+}
+
+fn import_bundle(path: &Path) -> Result<()> {
+    let project = bundle::import(path)?;
+    Journal::record(
+        format!("import-bundle {}", path.to_string_lossy()),
+        vec![Operation::ImportBundle {
+            uuid: project.uuid,
+            bundle: std::path::absolute(path)?,
+            data: Some(DirStats::collect(&project.get_current_data_path()?)?),
+        }],
+    )?;
+
+    weave()?;
+    println!("Imported {} ({})", project.name, project.uuid.hyphenated());
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, anything but an explicit yes counts as no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn find(query: &str, interactive: bool, data: bool) -> Result<()> {
+    let results = find::find_projects(query)?;
+    let result = if interactive && results.len() > 1 {
+        find::pick(&results)?
+    } else {
+        results.first()
+    }
+    .ok_or(anyhow!("No project matches {}", query))?;
+
+    println!(
+        "{}",
+        find::project_path(&result.project, data)?.to_string_lossy()
+    );
+    Ok(())
+}
+
+/// The project given by name or UUID, or the current project
+fn project_or_current(project: &Option<String>) -> Result<Project> {
+    match project {
+        Some(name_or_uuid) => Project::lookup(name_or_uuid),
+        None => Project::get_current_project()?.ok_or(anyhow!(
+            "Not in a project directory (or subdirectory thereof)!"
+        )),
+    }
+}
+
+fn info(project: &Option<String>) -> Result<()> {
+    info::print_info(&project_or_current(project)?)
+}
+
+fn archive(project: &Option<String>, compression: Option<bool>) -> Result<()> {
+    let project = project_or_current(project)?;
+    let (operations, result) = archive::archive(&project, compression);
+    if operations.is_empty() {
+        result?;
+        println!("{} is already archived", project.name);
+        return Ok(());
+    }
+
+    Journal::record(
+        format!(
+            "archive {}{}",
+            project.name,
+            match compression {
+                Some(true) => " --compress",
+                Some(false) => " --extract",
+                None => "",
+            }
+        ),
+        operations,
+    )?;
+    result?;
+    weave()?;
+    Ok(())
+}
+
+fn unarchive(project: &Option<String>) -> Result<()> {
+    let project = project_or_current(project)?;
+    let (operations, result) = archive::unarchive(&project);
+    if operations.is_empty() {
+        result?;
+        println!("{} is not archived", project.name);
+        return Ok(());
     }
+
+    Journal::record(format!("unarchive {}", project.name), operations)?;
+    result?;
+    weave()?;
+    Ok(())
+}
+
+fn link(from: &str, to: &str, kind: relations::RelationKind, remove: bool) -> Result<()> {
+    let from_project = Project::lookup(from)?;
+    let to_project = Project::lookup(to)?;
+    if from_project.uuid == to_project.uuid {
+        return Err(anyhow!("A project can't be related to itself"));
+    }
+
+    let operations = relations::set_relation(&from_project, &to_project, kind, remove)?;
+    if operations.is_empty() {
+        println!("Nothing to change");
+        return Ok(());
+    }
+
+    Journal::record(
+        format!(
+            "link {} {} --kind {}{}",
+            from,
+            to,
+            kind.name(),
+            if remove { " --remove" } else { "" }
+        ),
+        operations,
+    )?;
+    weave()?;
+    Ok(())
 }
 
 fn tags() -> Result<()> {
@@ -151,8 +693,140 @@ fn tags() -> Result<()> {
     ))?;
 
     let tags_file = current_project.get_tags_file_path()?;
+    let previous = std::fs::read_to_string(&tags_file)?;
+
+    if Project::edit_tags_file(&tags_file)? {
+        if std::fs::read_to_string(&tags_file)? != previous {
+            Journal::record(
+                format!("tags {}", current_project.name),
+                vec![Operation::EditTags {
+                    uuid: current_project.uuid,
+                    previous,
+                }],
+            )?;
+        }
+        weave()?;
+    }
+
+    Ok(())
+}
 
-    if open_in_editor(&tags_file)? {
+fn normalize_tags() -> Result<()> {
+    let mut operations = vec![];
+    for project in Project::list()? {
+        if let Some(previous) = project.normalize_tags_file()? {
+            println!(
+                "Normalized {} ({})",
+                project.name,
+                project.uuid.hyphenated()
+            );
+            operations.push(Operation::EditTags {
+                uuid: project.uuid,
+                previous,
+            });
+        }
+    }
+
+    println!("Normalized {} projects", operations.len());
+    if !operations.is_empty() {
+        Journal::record("tags normalize", operations)?;
+        weave()?;
+    }
+
+    Ok(())
+}
+
+fn retag(change: retag::TagChange, yes: bool) -> Result<()> {
+    apply_plan(&retag::RetagPlan::new(change)?, yes)
+}
+
+fn restore_metadata(snapshot: &Path, yes: bool) -> Result<()> {
+    apply_plan(&backup::RestorePlan::new(snapshot)?, yes)
+}
+
+/// Previews `plan`, applies it once confirmed and records it in the journal
+fn apply_plan(plan: &impl Plan, yes: bool) -> Result<()> {
+    if plan.is_empty() {
+        println!("Nothing to change");
+        return Ok(());
+    }
+
+    plan.print_preview();
+    if !yes && !confirm(&plan.confirmation())? {
+        return Ok(());
+    }
+
+    let (operations, result) = plan.apply();
+    Journal::record(plan.description(), operations)?;
+    result?;
+
+    // The schema may have changed, so the view tree must be built from the new one
+    Environment::reload()?;
+    weave()?;
+    Ok(())
+}
+
+fn migrate(to_toml: bool) -> Result<()> {
+    let env = Environment::get()?;
+
+    let mut operations = vec![];
+    let mut result = Ok(());
+    for project in Project::list()? {
+        let project_dir = env
+            .raw_storage_dir
+            .join(project.uuid.hyphenated().to_string());
+        let created = project.created()?.map(Into::into);
+        let previous = match metadata::migrate_project_file(&project_dir, created, to_toml)
+            .with_context(|| format!("Failed to migrate project {}", project.name))
+        {
+            Ok(Some(previous)) => previous,
+            Ok(None) => continue,
+            Err(e) => {
+                // The projects migrated so far can still be undone
+                result = Err(e);
+                break;
+            }
+        };
+
+        println!("Migrated {} ({})", project.name, project.uuid.hyphenated());
+        let uuid = project.uuid;
+        operations.push(if to_toml {
+            Operation::ConvertTagsFile { uuid, previous }
+        } else {
+            Operation::EditTags { uuid, previous }
+        });
+    }
+
+    println!("Migrated {} projects", operations.len());
+    Journal::record(
+        if to_toml { "migrate --toml" } else { "migrate" },
+        operations,
+    )?;
+    result
+}
+
+fn history() -> Result<()> {
+    for (i, entry) in Journal::load()?.entries().enumerate() {
+        println!(
+            "{:>4}  {}  {}",
+            i + 1,
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.description
+        );
+    }
+
+    Ok(())
+}
+
+fn undo(count: usize) -> Result<()> {
+    let undone = Journal::undo(count)?;
+    for entry in &undone {
+        println!("Undid \"{}\"", entry.description);
+    }
+
+    if undone.is_empty() {
+        println!("Nothing to undo");
+    } else {
         weave()?;
     }
 
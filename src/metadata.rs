@@ -0,0 +1,407 @@
+use crate::file_utils::{write_atomically, TEMP_FILE_SUFFIX};
+use crate::relations::Relation;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const TAGS_FILE_NAME: &str = "spiderman.tags";
+pub const METADATA_FILE_NAME: &str = "spiderman.toml";
+
+/// First line of a tags file in the current format. Files without it are read in the original
+/// format, where every `:` separates values.
+const TAGS_FORMAT_HEADER: &str = "# spiderman tags v2";
+const TAGS_FORMAT_HELP: &str =
+    "# One tag per line as name:value:value, put values containing : or # in \"quotes\"";
+
+pub type Tags = BTreeMap<String, Vec<String>>;
+
+/// Information about a project besides its tags, only stored in `spiderman.toml`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Local>>,
+    #[serde(default, skip_serializing_if = "ProjectState::is_active")]
+    pub state: ProjectState,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relations: Vec<Relation>,
+}
+
+/// Where a project is in its lifecycle
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectState {
+    #[default]
+    Active,
+    /// Only linked by schemas with `archive = true`
+    Archived,
+}
+
+impl ProjectState {
+    fn is_active(&self) -> bool {
+        *self == ProjectState::Active
+    }
+}
+
+/// Contents of `spiderman.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MetadataFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<DateTime<Local>>,
+    #[serde(default, skip_serializing_if = "ProjectState::is_active")]
+    state: ProjectState,
+    #[serde(default)]
+    tags: BTreeMap<String, TagValues>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    relations: Vec<Relation>,
+}
+
+/// Tag values in TOML files, either a single string or a list of strings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TagValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TagValues {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            TagValues::One(value) => std::slice::from_ref(value),
+            TagValues::Many(values) => values,
+        }
+    }
+}
+
+/// The file a project's tags are stored in. `spiderman.toml` takes precedence if a project has
+/// both, a project with neither gets a `spiderman.tags` file.
+pub fn project_file(project_dir: &Path) -> PathBuf {
+    let metadata_file = project_dir.join(METADATA_FILE_NAME);
+    if metadata_file.is_file() {
+        metadata_file
+    } else {
+        project_dir.join(TAGS_FILE_NAME)
+    }
+}
+
+/// Returns whether `name` is one of the files spiderman keeps next to a project's data, including
+/// the temporary files left behind by an interrupted write of one
+pub fn is_project_file(name: &str) -> bool {
+    let name = name.strip_suffix(TEMP_FILE_SUFFIX).unwrap_or(name);
+    name == TAGS_FILE_NAME || name == METADATA_FILE_NAME
+}
+
+/// Reads the tags and metadata of the project in `project_dir`
+pub fn read_project_file(project_dir: &Path) -> Result<(Tags, ProjectMetadata)> {
+    let path = project_file(project_dir);
+    parse_project_file(&path, &std::fs::read_to_string(&path)?)
+}
+
+/// Parses `contents` as the project file at `path`, which determines the format
+pub fn parse_project_file(path: &Path, contents: &str) -> Result<(Tags, ProjectMetadata)> {
+    if path.ends_with(METADATA_FILE_NAME) {
+        let file: MetadataFile = toml::de::from_str(contents)
+            .with_context(|| format!("Failed to parse {}", path.to_string_lossy()))?;
+        let tags = file
+            .tags
+            .into_iter()
+            .map(|(tag, values)| match values {
+                TagValues::One(value) => (tag, vec![value]),
+                TagValues::Many(values) => (tag, values),
+            })
+            .filter(|(_, values)| !values.is_empty())
+            .collect();
+        let metadata = ProjectMetadata {
+            description: file.description,
+            created: file.created,
+            state: file.state,
+            relations: file.relations,
+        };
+
+        Ok((tags, metadata))
+    } else {
+        let tags = parse_tags(contents)
+            .with_context(|| format!("Failed to parse {}", path.to_string_lossy()))?;
+        Ok((tags, ProjectMetadata::default()))
+    }
+}
+
+/// Writes `spiderman.toml` for a project
+pub fn write_metadata_file(path: &Path, tags: &Tags, metadata: &ProjectMetadata) -> Result<()> {
+    let file = MetadataFile {
+        description: metadata.description.clone(),
+        created: metadata.created,
+        state: metadata.state,
+        tags: tags
+            .iter()
+            .map(|(tag, values)| match values.as_slice() {
+                [value] => (tag.clone(), TagValues::One(value.clone())),
+                _ => (tag.clone(), TagValues::Many(values.clone())),
+            })
+            .collect(),
+        relations: metadata.relations.clone(),
+    };
+
+    write_atomically(path, toml::ser::to_string(&file)?.as_bytes())
+}
+
+/// Replaces the tags and metadata of the project in `project_dir`, keeping the format of its
+/// project file
+pub fn write_project_file(
+    project_dir: &Path,
+    tags: &Tags,
+    metadata: &ProjectMetadata,
+) -> Result<()> {
+    let path = project_file(project_dir);
+    if path.ends_with(METADATA_FILE_NAME) {
+        write_metadata_file(&path, tags, metadata)
+    } else {
+        write_atomically(&path, format_tags(tags).as_bytes())
+    }
+}
+
+/// Converts the tags file of the project in `project_dir` to the current format, or to
+/// `spiderman.toml` if `to_toml` is set, recording `created` if it is known. Returns the former
+/// contents of the tags file if anything had to be changed.
+pub fn migrate_project_file(
+    project_dir: &Path,
+    created: Option<DateTime<Local>>,
+    to_toml: bool,
+) -> Result<Option<String>> {
+    let path = project_file(project_dir);
+    if path.ends_with(METADATA_FILE_NAME) {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read_to_string(&path)?;
+    let tags = parse_tags(&contents)?;
+    if to_toml {
+        let metadata = ProjectMetadata {
+            created,
+            ..Default::default()
+        };
+        write_metadata_file(&project_dir.join(METADATA_FILE_NAME), &tags, &metadata)?;
+        std::fs::remove_file(&path)?;
+    } else {
+        if is_current_format(&contents) {
+            return Ok(None);
+        }
+        write_atomically(&path, format_tags(&tags).as_bytes())?;
+    }
+
+    Ok(Some(contents))
+}
+
+/// Whether the header of the current format is among the comments and empty lines at the start of
+/// `contents`
+fn is_current_format(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(|l| l.trim())
+        .take_while(|l| l.is_empty() || l.starts_with('#'))
+        .any(is_format_header)
+}
+
+/// Returns whether `line` marks a tags file in the current format
+pub fn is_format_header(line: &str) -> bool {
+    line.trim() == TAGS_FORMAT_HEADER
+}
+
+/// Parses the contents of a `spiderman.tags` file in either format
+pub fn parse_tags(contents: &str) -> Result<Tags> {
+    let is_v2 = is_current_format(contents);
+
+    let mut tag_map: Tags = BTreeMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let fields = if is_v2 {
+            parse_line(line).with_context(|| format!("Line {}", number + 1))?
+        } else {
+            line.split(':').map(|f| f.trim().to_string()).collect()
+        };
+
+        let mut fields = fields.into_iter();
+        if let Some(tag) = fields.next() {
+            if tag.is_empty() {
+                continue;
+            }
+
+            let mut values: Vec<_> = fields.filter(|v| !v.is_empty()).collect();
+            if values.is_empty() {
+                continue;
+            }
+
+            tag_map.entry(tag).or_default().append(&mut values);
+        }
+    }
+
+    Ok(tag_map)
+}
+
+/// Splits a line of the current format into its fields. A `\` takes the next character
+/// literally, as does everything between double quotes; an unquoted `#` starts a comment.
+fn parse_line(line: &str) -> Result<Vec<String>> {
+    // Characters are paired with whether they were escaped or quoted, which protects them from
+    // being trimmed
+    let mut fields = vec![];
+    let mut field: Vec<(char, bool)> = vec![];
+    let mut in_quotes = false;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or(anyhow!("Backslash at the end of the line"))?;
+                field.push((escaped, true));
+            }
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => field.push((c, true)),
+            '#' => break,
+            ':' => fields.push(finish_field(&mut field)),
+            _ => field.push((c, false)),
+        }
+    }
+
+    if in_quotes {
+        return Err(anyhow!("Missing closing quote"));
+    }
+    fields.push(finish_field(&mut field));
+
+    Ok(fields)
+}
+
+fn finish_field(field: &mut Vec<(char, bool)>) -> String {
+    let is_trimmed = |(c, literal): &(char, bool)| !literal && c.is_whitespace();
+    let start = field
+        .iter()
+        .position(|c| !is_trimmed(c))
+        .unwrap_or(field.len());
+    let end = field
+        .iter()
+        .rposition(|c| !is_trimmed(c))
+        .map_or(start, |i| i + 1);
+
+    let value = field[start..end].iter().map(|(c, _)| c).collect();
+    field.clear();
+    value
+}
+
+/// Formats tags in the current format of `spiderman.tags`
+pub fn format_tags(tags: &Tags) -> String {
+    let mut contents = format!("{}\n{}\n", TAGS_FORMAT_HEADER, TAGS_FORMAT_HELP);
+    for (tag, values) in tags {
+        let fields: Vec<_> = std::iter::once(tag)
+            .chain(values)
+            .map(|f| quote_field(f))
+            .collect();
+        contents.push_str(&fields.join(":"));
+        contents.push('\n');
+    }
+
+    contents
+}
+
+fn quote_field(field: &str) -> String {
+    let needs_quotes =
+        field.is_empty() || field.trim() != field || field.contains([':', '#', '"', '\\']);
+    if !needs_quotes {
+        return field.to_string();
+    }
+
+    format!("\"{}\"", field.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(entries: &[(&str, &[&str])]) -> Tags {
+        entries
+            .iter()
+            .map(|(tag, values)| {
+                (
+                    tag.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn format_and_parse_round_trip() {
+        let tags = tags(&[
+            ("type", &["Software", "Writing"]),
+            ("url", &["https://example.com:8080/a"]),
+            ("language", &["C#", "F# and more"]),
+            ("quote", &["say \"hi\"", "\""]),
+            ("path", &["C:\\Users\\me", "\\"]),
+            ("padded", &["  both  ", " leading", "trailing "]),
+            ("odd: name", &["value"]),
+        ]);
+
+        let contents = format_tags(&tags);
+        assert!(is_current_format(&contents));
+        assert_eq!(parse_tags(&contents).unwrap(), tags);
+    }
+
+    #[test]
+    fn parse_current_format() {
+        let contents = "# spiderman tags v2\n\
+            \n\
+            type: Software : Writing # a comment\n\
+            url:\"https://example.com\":a\\:b\n\
+            # organization:Ignored\n\
+            type:More\n";
+
+        assert_eq!(
+            parse_tags(contents).unwrap(),
+            tags(&[
+                ("type", &["Software", "Writing", "More"]),
+                ("url", &["https://example.com", "a:b"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_original_format() {
+        let contents = "type:Software:Writing\n organization : Uni \nlanguage:C#\nempty:\n";
+
+        assert!(!is_current_format(contents));
+        assert_eq!(
+            parse_tags(contents).unwrap(),
+            tags(&[
+                ("type", &["Software", "Writing"]),
+                ("organization", &["Uni"]),
+                ("language", &["C#"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn header_after_leading_comments() {
+        let contents = "# Notes about this project\n\n#\n# spiderman tags v2\nurl:\"a:b\"\n";
+
+        assert!(is_current_format(contents));
+        assert_eq!(parse_tags(contents).unwrap(), tags(&[("url", &["a:b"])]));
+    }
+
+    #[test]
+    fn header_after_tags_is_ignored() {
+        let contents = "url:a:b\n# spiderman tags v2\n";
+
+        assert!(!is_current_format(contents));
+        assert_eq!(parse_tags(contents).unwrap(), tags(&[("url", &["a", "b"])]));
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(parse_line("url:\"a:b").is_err());
+        assert!(parse_line("path:C:\\").is_err());
+    }
+}
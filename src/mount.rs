@@ -0,0 +1,341 @@
+use crate::weave::ViewPlan;
+use crate::Environment;
+use anyhow::{anyhow, Result};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, Request,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL: Duration = Duration::from_secs(1);
+const ROOT_INODE: u64 = 1;
+
+/// Mounts the view tree at `mountpoint` and blocks until the file system is unmounted
+pub fn mount(mountpoint: &Path) -> Result<()> {
+    let filesystem = ViewFilesystem::new()?;
+    let options = [
+        MountOption::RO,
+        MountOption::FSName("spiderman".to_string()),
+        MountOption::DefaultPermissions,
+    ];
+
+    fuser::mount2(filesystem, mountpoint, &options)?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Node {
+    /// Directory of the view tree that only exists in memory
+    Virtual {
+        parent: u64,
+        children: BTreeMap<OsString, u64>,
+    },
+    /// File or directory inside a project's raw data directory
+    Passthrough { parent: u64, path: PathBuf },
+}
+
+/// Read-only file system serving the view tree, with the project data directories as leaves
+struct ViewFilesystem {
+    nodes: Vec<Node>,
+    passthrough_inodes: HashMap<PathBuf, u64>,
+    uid: u32,
+    gid: u32,
+    mounted_at: SystemTime,
+}
+
+impl ViewFilesystem {
+    fn new() -> Result<Self> {
+        let env = Environment::get()?;
+        let base_metadata = env.base_path.metadata()?;
+
+        let mut filesystem = Self {
+            nodes: vec![Node::Virtual {
+                parent: ROOT_INODE,
+                children: BTreeMap::new(),
+            }],
+            passthrough_inodes: HashMap::new(),
+            uid: base_metadata.uid(),
+            gid: base_metadata.gid(),
+            mounted_at: SystemTime::now(),
+        };
+
+        for link in ViewPlan::new()?.resolve(|_| false)? {
+            let relative = link.link.strip_prefix(&env.base_path)?;
+            let mut components: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_owned())
+                .collect();
+            let leaf_name = components
+                .pop()
+                .ok_or(anyhow!("Empty view path for project {}", link.uuid))?;
+
+            let mut parent = ROOT_INODE;
+            for component in components {
+                parent = filesystem.virtual_child(parent, component)?;
+            }
+
+            let leaf = filesystem.passthrough_inode(parent, link.target);
+            if let Node::Virtual { children, .. } = filesystem.node_mut(parent)? {
+                children.insert(leaf_name, leaf);
+            }
+        }
+
+        Ok(filesystem)
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get(ino.checked_sub(1)? as usize)
+    }
+
+    fn node_mut(&mut self, ino: u64) -> Result<&mut Node> {
+        self.nodes
+            .get_mut((ino - 1) as usize)
+            .ok_or(anyhow!("Unknown inode {}", ino))
+    }
+
+    fn push_node(&mut self, node: Node) -> u64 {
+        self.nodes.push(node);
+        self.nodes.len() as u64
+    }
+
+    /// Returns the virtual directory `name` below `parent`, creating it if necessary
+    fn virtual_child(&mut self, parent: u64, name: OsString) -> Result<u64> {
+        if let Node::Virtual { children, .. } = self.node_mut(parent)? {
+            if let Some(&ino) = children.get(&name) {
+                return Ok(ino);
+            }
+        } else {
+            return Err(anyhow!(
+                "View path component {} collides with a project",
+                name.to_string_lossy()
+            ));
+        }
+
+        let ino = self.push_node(Node::Virtual {
+            parent,
+            children: BTreeMap::new(),
+        });
+        if let Node::Virtual { children, .. } = self.node_mut(parent)? {
+            children.insert(name, ino);
+        }
+
+        Ok(ino)
+    }
+
+    /// Returns the inode for a path within the project data, allocating it on first use
+    fn passthrough_inode(&mut self, parent: u64, path: PathBuf) -> u64 {
+        if let Some(&ino) = self.passthrough_inodes.get(&path) {
+            return ino;
+        }
+
+        let ino = self.push_node(Node::Passthrough {
+            parent,
+            path: path.clone(),
+        });
+        self.passthrough_inodes.insert(path, ino);
+        ino
+    }
+
+    fn virtual_attr(&self, ino: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            crtime: self.mounted_at,
+            kind: FileType::Directory,
+            perm: 0o555,
+            nlink: 2,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        match self.node(ino)? {
+            Node::Virtual { .. } => Some(self.virtual_attr(ino)),
+            Node::Passthrough { path, .. } => path
+                .symlink_metadata()
+                .ok()
+                .map(|m| passthrough_attr(ino, &m)),
+        }
+    }
+}
+
+fn passthrough_attr(ino: u64, metadata: &Metadata) -> FileAttr {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::Symlink
+    } else {
+        FileType::RegularFile
+    };
+    let time = |secs: i64, nsecs: i64| {
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::new(secs as u64, nsecs as u32)
+        } else {
+            UNIX_EPOCH
+        }
+    };
+
+    FileAttr {
+        ino,
+        size: metadata.size(),
+        blocks: metadata.blocks(),
+        atime: time(metadata.atime(), metadata.atime_nsec()),
+        mtime: time(metadata.mtime(), metadata.mtime_nsec()),
+        ctime: time(metadata.ctime(), metadata.ctime_nsec()),
+        crtime: metadata.created().unwrap_or(UNIX_EPOCH),
+        kind,
+        // Strip write permissions, the file system is read-only
+        perm: (metadata.mode() & 0o555) as u16,
+        nlink: metadata.nlink() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        rdev: metadata.rdev() as u32,
+        blksize: metadata.blksize() as u32,
+        flags: 0,
+    }
+}
+
+impl Filesystem for ViewFilesystem {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.node(parent) {
+            Some(Node::Virtual { children, .. }) => children.get(name).copied(),
+            Some(Node::Passthrough { path, .. }) => {
+                let path = path.join(name);
+                if path.symlink_metadata().is_ok() {
+                    Some(self.passthrough_inode(parent, path))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        match ino.and_then(|ino| self.attr(ino)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.node(ino) {
+            Some(Node::Passthrough { path, .. }) => match path.read_link() {
+                Ok(target) => reply.data(target.as_os_str().as_bytes()),
+                Err(e) => reply.error(e.raw_os_error().unwrap_or(libc::EIO)),
+            },
+            _ => reply.error(libc::EINVAL),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        match self.node(ino) {
+            Some(Node::Passthrough { .. }) => reply.opened(0, 0),
+            Some(Node::Virtual { .. }) => reply.error(libc::EISDIR),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let path = match self.node(ino) {
+            Some(Node::Passthrough { path, .. }) => path,
+            _ => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+
+        let mut buffer = vec![0; size as usize];
+        match File::open(path).and_then(|f| f.read_at(&mut buffer, offset as u64)) {
+            Ok(read) => reply.data(&buffer[..read]),
+            Err(e) => reply.error(e.raw_os_error().unwrap_or(libc::EIO)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let mut entries = vec![];
+        match self.node(ino) {
+            Some(Node::Virtual { parent, children }) => {
+                entries.push((ino, FileType::Directory, OsString::from(".")));
+                entries.push((*parent, FileType::Directory, OsString::from("..")));
+                for (name, &child) in children {
+                    let kind = self.attr(child).map_or(FileType::Directory, |a| a.kind);
+                    entries.push((child, kind, name.clone()));
+                }
+            }
+            Some(Node::Passthrough { parent, path }) => {
+                let (parent, path) = (*parent, path.clone());
+                let read_dir = match path.read_dir() {
+                    Ok(r) => r,
+                    Err(e) => {
+                        reply.error(e.raw_os_error().unwrap_or(libc::EIO));
+                        return;
+                    }
+                };
+
+                entries.push((ino, FileType::Directory, OsString::from(".")));
+                entries.push((parent, FileType::Directory, OsString::from("..")));
+                for entry in read_dir.filter_map(|e| e.ok()) {
+                    let child = self.passthrough_inode(ino, entry.path());
+                    let kind = self.attr(child).map_or(FileType::RegularFile, |a| a.kind);
+                    entries.push((child, kind, entry.file_name()));
+                }
+            }
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        }
+
+        for (i, (child, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // The offset passed to add is the offset of the next entry
+            if reply.add(child, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
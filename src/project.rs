@@ -1,22 +1,49 @@
-use crate::file_utils::{current_dir_with_symlinks, open_in_editor};
+use crate::file_utils::{current_dir_with_symlinks, open_in_editor, write_atomically};
+use crate::index::ProjectIndex;
+use crate::journal::Operation;
+use crate::metadata::{
+    format_tags, is_format_header, is_project_file, migrate_project_file, parse_project_file,
+    project_file, read_project_file, write_project_file, ProjectMetadata, Tags, METADATA_FILE_NAME,
+};
+use crate::registry::{normalize_tags, validate_tags};
+use crate::weave::managed_link_target;
 use crate::Environment;
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::fs::{DirEntry, File, ReadDir};
-use std::io::{BufRead, BufReader, Write};
-use std::iter::FilterMap;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+/// Appended to the project name for the tarball holding the data of a compressed project
+pub const COMPRESSED_DATA_EXTENSION: &str = ".tar.zst";
+
+/// Tag naming the project whose tags a project inherits, by name or UUID
+pub const PARENT_TAG: &str = "parent";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Project {
     pub uuid: Uuid,
     pub name: String,
-    pub tags: HashMap<String, Vec<String>>,
+    /// The project data is stored as a compressed tarball, see [`crate::archive`]
+    #[serde(default)]
+    pub compressed: bool,
+    /// Effective tags, the project's own tags along with those it inherits from its parent
+    #[serde(skip)]
+    pub tags: Tags,
+    /// Tags as written in the project's tags file
+    #[serde(rename = "tags")]
+    pub own_tags: Tags,
+    /// Name of the parent project, if the project has one
+    #[serde(skip)]
+    pub parent_name: Option<String>,
+    #[serde(default)]
+    pub metadata: ProjectMetadata,
 }
 
-const SPIDERMAN_PROJECT_INFO_FILE_NAME: &'static str = "spiderman.tags";
-
 impl Project {
     pub fn new(name: &str) -> Result<Self> {
         let uuid = Uuid::new_v4();
@@ -28,61 +55,171 @@ impl Project {
         path.push(name);
         std::fs::create_dir(&path)?;
         path.pop();
-        path.push(SPIDERMAN_PROJECT_INFO_FILE_NAME);
-        let mut tag_file = File::create(&path)?;
+        // If we're in a directory that matches a schema, pre-populate the tags file
+        Self::write_tags_file(
+            &Self::find_tags_file(&path),
+            &Self::tags_from_current_dir()?,
+        )?;
+
+        Self::edit_tags_file(&Self::find_tags_file(&path))?;
+        let (tags, metadata) = Self::read_tags(&path)?;
+
+        Self::resolved(Self {
+            uuid,
+            name: name.to_owned(),
+            compressed: false,
+            tags: tags.clone(),
+            own_tags: tags,
+            parent_name: None,
+            metadata,
+        })
+    }
+
+    /// Tags inferred from the current directory, if it lies within the view tree of a schema
+    pub fn tags_from_current_dir() -> Result<BTreeMap<String, String>> {
+        let env = Environment::get()?;
         let current_dir = current_dir_with_symlinks()?;
 
-        // If we're in a directory that matches a schema, pre-populate the tags file
-        if let Some(tags) = env
+        Ok(env
             .schema
             .schemas
             .iter()
             .map(|s| s.match_with_dir(&current_dir))
             .find_map(|t| t.unwrap_or(None))
-        {
-            for (tag, value) in tags {
-                let line = format!("{}:{}\r\n", tag, value);
-                tag_file.write_all(line.as_bytes())?;
-            }
-        }
+            .unwrap_or_default())
+    }
+
+    /// Writes a new tags file with a single value for each tag
+    pub fn write_tags_file(path: &Path, tags: &BTreeMap<String, String>) -> Result<()> {
+        let tags: Tags = tags
+            .iter()
+            .map(|(tag, value)| (tag.clone(), vec![value.clone()]))
+            .collect();
+
+        let mut tag_file = File::create(path)?;
+        tag_file.write_all(format_tags(&tags).as_bytes())?;
         tag_file.sync_data()?;
-        drop(tag_file); // Close the file
 
-        open_in_editor(&path)?;
-        let tags = Self::read_tags(&path)?;
+        Ok(())
+    }
 
-        Ok(Self {
-            uuid,
-            name: name.to_owned(),
-            tags,
-        })
+    /// Opens a tags file in the editor until it is valid. Problems are shown as comments at the
+    /// top of the file; saving it unchanged gives up and keeps the file as it is. Returns whether
+    /// an editor could be opened.
+    pub fn edit_tags_file(path: &Path) -> Result<bool> {
+        let mut previous_attempt: Option<String> = None;
+        loop {
+            if !open_in_editor(path)? {
+                return Ok(false);
+            }
+
+            let contents = strip_error_comments(&std::fs::read_to_string(path)?);
+            let errors = match parse_project_file(path, &contents) {
+                Ok((tags, _)) => {
+                    let registry = &Environment::get()?.schema.tags;
+                    validate_tags(registry, &normalize_tags(registry, tags))
+                }
+                Err(e) => vec![format!("{:#}", e)],
+            };
+
+            if errors.is_empty() || previous_attempt.as_ref() == Some(&contents) {
+                for error in &errors {
+                    eprintln!("WARNING: {}: {}", path.to_string_lossy(), error);
+                }
+                write_atomically(path, contents.as_bytes())?;
+                return Ok(true);
+            }
+
+            write_atomically(path, with_error_comments(&contents, &errors).as_bytes())?;
+            previous_attempt = Some(contents);
+        }
     }
 
+    /// Opens the project in the UUID directory `path`, with the tags it inherits from its parent
     pub fn open(path: &Path) -> Result<Self> {
+        Self::resolved(Self::read(path)?)
+    }
+
+    /// Looks up the parents of `project` if it has any. Only the chain of parents is read, they
+    /// are found through the project index.
+    fn resolved(project: Self) -> Result<Self> {
+        if !project.own_tags.contains_key(PARENT_TAG) {
+            return Ok(project);
+        }
+
+        let env = Environment::get()?;
+        let index = ProjectIndex::load()?;
+        let mut chain = vec![project];
+        while let Some(parent) = chain.last().unwrap().own_tags.get(PARENT_TAG) {
+            let candidates = index.find(&parent[0]);
+            if candidates.is_empty() {
+                // The index may not know the parent yet
+                let uuid = chain[0].uuid;
+                return Ok(Self::list()?
+                    .find(|p| p.uuid == uuid)
+                    .unwrap_or_else(|| chain.swap_remove(0)));
+            }
+
+            // All candidates are read, so that ambiguous parents and cycles are reported the
+            // same way as when resolving all projects
+            let length = chain.len();
+            let unread: Vec<&Uuid> = candidates
+                .iter()
+                .filter(|u| chain.iter().all(|p| p.uuid != **u))
+                .collect();
+            for uuid in unread {
+                if let Ok(project) =
+                    Self::read(&env.raw_storage_dir.join(uuid.hyphenated().to_string()))
+                {
+                    chain.push(project);
+                }
+            }
+            if candidates.len() != 1 || chain.len() == length {
+                break;
+            }
+        }
+
+        Self::resolve_inheritance(&mut chain);
+        Ok(chain.swap_remove(0))
+    }
+
+    /// Opens the project in the UUID directory `path` with only its own tags
+    pub fn read(path: &Path) -> Result<Self> {
         let uuid_str = path
             .file_name()
             .ok_or(anyhow!("Invalid project path"))?
             .to_string_lossy();
         let uuid = Uuid::parse_str(uuid_str.as_ref())?;
 
-        let mut dir = path.to_path_buf();
-        dir.push(SPIDERMAN_PROJECT_INFO_FILE_NAME);
-        return if dir.metadata().is_ok_and(|m| m.is_file()) {
-            let tags = Self::read_tags(&dir)?;
+        let file = Self::find_tags_file(path);
+        return if file.metadata().is_ok_and(|m| m.is_file()) {
+            let (tags, metadata) = Self::read_tags(path)?;
 
-            dir.pop();
-            let directory_contents: Vec<_> = dir
+            let directory_contents: Vec<_> = path
                 .read_dir()?
                 .filter_map(|e| e.ok())
-                .filter(|d| d.file_name().to_string_lossy() != SPIDERMAN_PROJECT_INFO_FILE_NAME)
+                .filter(|d| !is_project_file(&d.file_name().to_string_lossy()))
                 .collect();
 
             if directory_contents.len() == 1 {
-                let name = directory_contents[0]
+                let file_name = directory_contents[0]
                     .file_name()
                     .to_string_lossy()
                     .to_string();
-                Ok(Self { uuid, name, tags })
+                let is_file = directory_contents[0].file_type()?.is_file();
+                let (name, compressed) = match file_name.strip_suffix(COMPRESSED_DATA_EXTENSION) {
+                    Some(name) if is_file => (name.to_string(), true),
+                    _ => (file_name, false),
+                };
+                Ok(Self {
+                    uuid,
+                    name,
+                    compressed,
+                    tags: tags.clone(),
+                    own_tags: tags,
+                    parent_name: None,
+                    metadata,
+                })
             } else {
                 Err(anyhow!(
                     "More than one subdirectory in project UUID directory"
@@ -91,65 +228,242 @@ impl Project {
         } else {
             Err(anyhow!(
                 "No spiderman tags file in UUID directory: {}",
-                dir.to_string_lossy()
+                path.to_string_lossy()
             ))
         };
     }
 
-    fn read_tags(path: &Path) -> Result<HashMap<String, Vec<String>>> {
-        let tags_file = File::open(&path)?;
-        tags_file.sync_data()?;
-        let tags_reader = BufReader::new(tags_file);
-        let mut tag_map: HashMap<String, Vec<String>> = HashMap::new();
-        for line in tags_reader.lines() {
-            let line = line?;
-            let mut tags = line.split(':');
-            if let Some(tag) = tags.next() {
-                let tag = tag.trim().to_string();
-                if tag.is_empty() {
-                    continue;
-                }
+    /// Reads the tags and metadata of the project in `project_dir`, replacing aliases and warning
+    /// about tags that don't match the tag registry
+    fn read_tags(project_dir: &Path) -> Result<(Tags, ProjectMetadata)> {
+        let registry = &Environment::get()?.schema.tags;
+        let (tags, metadata) = read_project_file(project_dir)?;
+        let tags = normalize_tags(registry, tags);
 
-                let mut values: Vec<_> = tags.map(|v| v.trim().to_string()).collect();
-                if values.is_empty() {
-                    continue;
-                }
+        for error in validate_tags(registry, &tags) {
+            eprintln!(
+                "WARNING: {}: {}",
+                project_file(project_dir).to_string_lossy(),
+                error
+            );
+        }
 
-                if let Some(tag_vec) = tag_map.get_mut(&tag) {
-                    tag_vec.append(&mut values);
-                } else {
-                    tag_map.insert(tag, values);
-                }
-            }
+        Ok((tags, metadata))
+    }
+
+    /// Rewrites the tags file of the project with aliases replaced. Returns the previous contents
+    /// of the file if it had to be changed.
+    pub fn normalize_tags_file(&self) -> Result<Option<String>> {
+        let mut project_dir = self.get_project_raw_data_path()?;
+        project_dir.pop();
+
+        let (tags, metadata) = read_project_file(&project_dir)?;
+        let normalized = normalize_tags(&Environment::get()?.schema.tags, tags.clone());
+        if normalized == tags {
+            return Ok(None);
         }
 
-        return Ok(tag_map);
+        let path = project_file(&project_dir);
+        let previous = std::fs::read_to_string(&path)?;
+        write_project_file(&project_dir, &normalized, &metadata)?;
+        Ok(Some(previous))
     }
 
+    /// Lists all projects in the current project root, ordered by UUID.
+    ///
+    /// Projects are read from the project index, only projects that changed since the index was
+    /// last refreshed are read from disk.
     pub fn list() -> Result<ProjectIterator> {
-        let map_to_option = Box::new(|e: std::io::Result<DirEntry>| e.ok())
-            as Box<dyn Fn(std::io::Result<DirEntry>) -> Option<DirEntry>>;
-        let map_to_project = Box::new(|d: DirEntry| {
-            let path = d.path();
-            match Self::open(&path) {
-                Ok(p) => Some(p),
-                Err(e) => {
+        Ok(ProjectIndex::load()?.refresh()?.into_iter())
+    }
+
+    /// Changes the metadata of the project with `update`. Returns the operations reversing the
+    /// change, which are empty if there was nothing to change.
+    ///
+    /// Metadata can only be stored in `spiderman.toml`, so a `spiderman.tags` file is converted
+    /// first.
+    pub fn update_metadata(&self, update: impl Fn(&mut ProjectMetadata)) -> Result<Vec<Operation>> {
+        let mut project_dir = self.get_project_raw_data_path()?;
+        project_dir.pop();
+
+        let (_, metadata) = read_project_file(&project_dir)?;
+        let mut updated = metadata.clone();
+        update(&mut updated);
+        if updated == metadata {
+            return Ok(vec![]);
+        }
+
+        let mut operations = vec![];
+        let path = project_file(&project_dir);
+        if !path.ends_with(METADATA_FILE_NAME) {
+            let previous = std::fs::read_to_string(&path)?;
+            migrate_project_file(&project_dir, self.created()?.map(Into::into), true)?;
+            println!(
+                "Converted the tags file of {} to {}",
+                self.name, METADATA_FILE_NAME
+            );
+            operations.push(Operation::ConvertTagsFile {
+                uuid: self.uuid,
+                previous,
+            });
+        }
+
+        let previous = std::fs::read_to_string(project_file(&project_dir))?;
+        let (tags, mut metadata) = read_project_file(&project_dir)?;
+        update(&mut metadata);
+        write_project_file(&project_dir, &tags, &metadata)?;
+        operations.push(Operation::EditTags {
+            uuid: self.uuid,
+            previous,
+        });
+
+        Ok(operations)
+    }
+
+    /// Sets the effective tags of all `projects`, which inherit every tag they don't set themselves
+    /// from their parent. Parents are given by name or UUID (prefix) in the `parent` tag.
+    pub fn resolve_inheritance(projects: &mut [Project]) {
+        // Parents are looked up by name, or by UUID prefix among the sorted UUIDs
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, project) in projects.iter().enumerate() {
+            by_name.entry(&project.name).or_default().push(index);
+        }
+        let mut uuids: Vec<(String, usize)> = projects
+            .iter()
+            .enumerate()
+            .map(|(index, project)| (project.uuid.hyphenated().to_string(), index))
+            .collect();
+        uuids.sort();
+        let find = |name_or_uuid: &str| {
+            let mut found = by_name.get(name_or_uuid).cloned().unwrap_or_default();
+            if name_or_uuid.len() >= 8 {
+                let start = uuids.partition_point(|(uuid, _)| uuid.as_str() < name_or_uuid);
+                for (_, index) in uuids[start..]
+                    .iter()
+                    .take_while(|(uuid, _)| uuid.starts_with(name_or_uuid))
+                {
+                    if !found.contains(index) {
+                        found.push(*index);
+                    }
+                }
+            }
+            found
+        };
+
+        let parents: Vec<Option<usize>> = projects
+            .iter()
+            .map(|project| {
+                let parent = project.own_tags.get(PARENT_TAG)?;
+                if parent.len() > 1 {
                     eprintln!(
-                        "WARNING: Ignoring project at {}: {}",
-                        path.to_string_lossy(),
-                        e
+                        "WARNING: Project {} has more than one parent, only {} is used",
+                        project.name, parent[0]
                     );
-                    None
                 }
+                match find(&parent[0]).as_slice() {
+                    [index] => Some(*index),
+                    [] => {
+                        eprintln!(
+                            "WARNING: Parent {} of project {} does not exist",
+                            parent[0], project.name
+                        );
+                        None
+                    }
+                    _ => {
+                        eprintln!(
+                            "WARNING: Parent {} of project {} is ambiguous, use its UUID",
+                            parent[0], project.name
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let mut resolved = vec![false; projects.len()];
+        for start in 0..projects.len() {
+            // Follow the parents up to a resolved project or the root, then resolve on the way
+            // back down
+            let mut chain = vec![start];
+            let mut current = start;
+            while let Some(parent) = parents[current].filter(|p| !resolved[*p]) {
+                if chain.contains(&parent) {
+                    eprintln!(
+                        "WARNING: Projects {} inherit from each other, ignoring the parent of {}",
+                        chain.iter().map(|i| projects[*i].name.as_str()).join(", "),
+                        projects[current].name
+                    );
+                    break;
+                }
+                chain.push(parent);
+                current = parent;
             }
-        }) as Box<dyn Fn(DirEntry) -> Option<Project>>;
 
-        let env = Environment::get()?;
-        Ok(env
-            .raw_storage_dir
-            .read_dir()?
-            .filter_map(map_to_option)
-            .filter_map(map_to_project))
+            for index in chain.into_iter().rev() {
+                if resolved[index] {
+                    continue;
+                }
+                let mut tags = projects[index].own_tags.clone();
+                let mut parent_name = None;
+                if let Some(parent) = parents[index].filter(|p| resolved[*p]) {
+                    for (tag, values) in &projects[parent].tags {
+                        if tag != PARENT_TAG && !tags.contains_key(tag) {
+                            tags.insert(tag.clone(), values.clone());
+                        }
+                    }
+                    parent_name = Some(projects[parent].name.clone());
+                }
+                projects[index].tags = tags;
+                projects[index].parent_name = parent_name;
+                resolved[index] = true;
+            }
+        }
+    }
+
+    /// Tags inherited from the parent, which the project doesn't set itself
+    pub fn inherited_tags(&self) -> Tags {
+        self.tags
+            .iter()
+            .filter(|(tag, _)| !self.own_tags.contains_key(*tag))
+            .map(|(tag, values)| (tag.clone(), values.clone()))
+            .collect()
+    }
+
+    /// Returns whether `name_or_uuid` is the name, the UUID or a UUID prefix of eight or more
+    /// characters of this project
+    pub fn matches(&self, name_or_uuid: &str) -> bool {
+        self.name == name_or_uuid
+            || (name_or_uuid.len() >= 8
+                && self
+                    .uuid
+                    .hyphenated()
+                    .encode_lower(&mut Uuid::encode_buffer())
+                    .starts_with(name_or_uuid))
+    }
+
+    /// Finds a project by its name, its UUID or the first eight or more characters of its UUID
+    pub fn lookup(name_or_uuid: &str) -> Result<Self> {
+        let matches: Vec<_> = Self::list()?.filter(|p| p.matches(name_or_uuid)).collect();
+
+        match matches.len() {
+            0 => Err(anyhow!("There is no project {}", name_or_uuid)),
+            1 => Ok(matches.into_iter().next().unwrap()),
+            _ => Err(anyhow!(
+                "{} is ambiguous, use one of the UUIDs {}",
+                name_or_uuid,
+                matches
+                    .iter()
+                    .map(|p| p.uuid.hyphenated().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Path of the tags file within a project's UUID directory, either `spiderman.toml` or
+    /// `spiderman.tags`
+    pub fn find_tags_file(project_dir: &Path) -> PathBuf {
+        project_file(project_dir)
     }
 
     pub fn get_project_raw_data_path(&self) -> Result<PathBuf> {
@@ -162,21 +476,57 @@ impl Project {
         return Ok(path);
     }
 
+    /// Path of the tarball holding the project data while the project is compressed
+    pub fn get_compressed_data_path(&self) -> Result<PathBuf> {
+        let mut path = self.get_project_raw_data_path()?;
+        path.set_file_name(format!("{}{}", self.name, COMPRESSED_DATA_EXTENSION));
+        Ok(path)
+    }
+
+    /// Path the project data can currently be found at, the tarball if the project is compressed
+    pub fn get_current_data_path(&self) -> Result<PathBuf> {
+        if self.compressed {
+            self.get_compressed_data_path()
+        } else {
+            self.get_project_raw_data_path()
+        }
+    }
+
     pub fn get_current_project() -> Result<Option<Self>> {
         let env = Environment::get()?;
-        let raw_storage_path = env.raw_storage_dir.canonicalize()?;
         let current_path = current_dir_with_symlinks()?;
-        let mut project_canonical_path = None;
+
+        // Links recorded by the last weave can be matched without touching the file system
+        if let Some(uuid) = ProjectIndex::load()?.project_at(&current_path)? {
+            return Ok(Some(Self::open(
+                &env.raw_storage_dir.join(uuid.hyphenated().to_string()),
+            )?));
+        }
+
+        // Within the raw data directory, the project is given by the UUID directory. Staging and
+        // temporary directories there don't belong to any project.
+        let raw_storage_dir = env.raw_storage_dir.canonicalize()?;
+        if let Ok(relative_path) = std::env::current_dir()?.strip_prefix(&raw_storage_dir) {
+            return match relative_path.components().next() {
+                Some(uuid_dir)
+                    if Uuid::parse_str(&uuid_dir.as_os_str().to_string_lossy()).is_ok() =>
+                {
+                    Ok(Some(Self::open(&env.raw_storage_dir.join(uuid_dir))?))
+                }
+                _ => Ok(None),
+            };
+        }
+
+        let mut project_data_path = None;
         for path in current_path.ancestors() {
             if path.is_symlink() {
-                let canonical = path.canonicalize()?;
-                if canonical.starts_with(&raw_storage_path) {
-                    project_canonical_path = Some(canonical);
+                if let Some(target) = managed_link_target(path)? {
+                    project_data_path = Some(target);
                 }
             }
         }
 
-        match project_canonical_path {
+        match project_data_path {
             None => Ok(None),
             Some(path) => Ok(Some(Self::open(
                 path.parent().expect("No parent directory"),
@@ -184,10 +534,28 @@ impl Project {
         }
     }
 
+    /// Returns when the project was created. Unless `spiderman.toml` records it, this is the
+    /// creation time of the UUID directory, which is unknown on file systems that don't record
+    /// creation times, such as NFS.
+    pub fn created(&self) -> Result<Option<SystemTime>> {
+        if let Some(created) = self.metadata.created {
+            return Ok(Some(created.into()));
+        }
+
+        let mut path = self.get_project_raw_data_path()?;
+        path.pop();
+
+        Ok(path
+            .metadata()?
+            .created()
+            .ok()
+            .filter(|&created| created > UNIX_EPOCH))
+    }
+
     pub fn get_tags_file_path(&self) -> Result<PathBuf> {
-        let mut tags_file = self.get_project_raw_data_path()?;
-        tags_file.pop();
-        tags_file.push(SPIDERMAN_PROJECT_INFO_FILE_NAME);
+        let mut project_dir = self.get_project_raw_data_path()?;
+        project_dir.pop();
+        let tags_file = Self::find_tags_file(&project_dir);
 
         if !(tags_file.exists() && tags_file.is_file()) {
             Err(anyhow!(
@@ -200,7 +568,29 @@ impl Project {
     }
 }
 
-pub type ProjectIterator = FilterMap<
-    FilterMap<ReadDir, Box<dyn Fn(std::io::Result<DirEntry>) -> Option<DirEntry>>>,
-    Box<dyn Fn(DirEntry) -> Option<Project>>,
->;
+pub type ProjectIterator = std::vec::IntoIter<Project>;
+
+const ERROR_COMMENT_PREFIX: &str = "# ERROR: ";
+
+fn strip_error_comments(contents: &str) -> String {
+    contents
+        .split_inclusive('\n')
+        .filter(|l| !l.starts_with(ERROR_COMMENT_PREFIX))
+        .collect()
+}
+
+/// Puts `errors` at the top of a tags file, but after the format header, which has to stay the
+/// first line
+fn with_error_comments(contents: &str, errors: &[String]) -> String {
+    let comments: String = errors
+        .iter()
+        .map(|e| format!("{}{}\n", ERROR_COMMENT_PREFIX, e.replace('\n', " ")))
+        .collect();
+
+    match contents.split_once('\n') {
+        Some((first_line, rest)) if is_format_header(first_line) => {
+            format!("{}\n{}{}", first_line, comments, rest)
+        }
+        _ => comments + contents,
+    }
+}
@@ -0,0 +1,217 @@
+use crate::metadata::Tags;
+use crate::project::PARENT_TAG;
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// Known tags, declared as `[tags.<name>]` tables in `schema.toml`. As soon as one tag is
+/// declared with more than aliases, tags that aren't are reported as unknown.
+pub type TagRegistry = BTreeMap<String, TagDefinition>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Every project must have this tag
+    #[serde(default)]
+    pub required: bool,
+    /// A project may have more than one value for this tag
+    #[serde(default = "default_multiple")]
+    pub multiple: bool,
+    #[serde(default, rename = "type")]
+    pub tag_type: TagType,
+    /// The only values allowed for this tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    /// Regular expression every value must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Other names of this tag, which are replaced by its name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Other spellings of values, by the value they are replaced with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub value_aliases: BTreeMap<String, Vec<String>>,
+    /// `pattern`, compiled on first use
+    #[serde(skip)]
+    compiled_pattern: OnceLock<Option<Result<Regex, regex::Error>>>,
+}
+
+fn default_multiple() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagType {
+    #[default]
+    String,
+    /// A date written as `YYYY-MM-DD`
+    Date,
+    Integer,
+    /// `true` or `false`
+    Boolean,
+}
+
+/// Replaces aliases of tag names and values by their canonical form. Aliases are matched
+/// ignoring case, as are the canonical names themselves.
+pub fn normalize_tags(registry: &TagRegistry, tags: Tags) -> Tags {
+    let mut normalized: Tags = BTreeMap::new();
+    for (tag, values) in tags {
+        let (tag, definition) = match registry.iter().find(|(name, definition)| {
+            name.eq_ignore_ascii_case(&tag)
+                || definition
+                    .aliases
+                    .iter()
+                    .any(|a| a.eq_ignore_ascii_case(&tag))
+        }) {
+            Some((name, definition)) => (name.clone(), Some(definition)),
+            None => (tag, None),
+        };
+
+        let canonical_values = normalized.entry(tag).or_default();
+        for value in values {
+            let value = definition
+                .and_then(|d| d.canonical_value(&value))
+                .unwrap_or(value);
+            if !canonical_values.contains(&value) {
+                canonical_values.push(value);
+            }
+        }
+    }
+
+    normalized
+}
+
+/// Checks `tags` against the registry and returns a description of every violation
+pub fn validate_tags(registry: &TagRegistry, tags: &Tags) -> Vec<String> {
+    let mut errors = vec![];
+    // Declaring aliases alone doesn't make the registry complete
+    let check_unknown = registry.values().any(|d| !d.is_alias_only());
+
+    for (tag, definition) in registry {
+        if definition.required && !tags.contains_key(tag) {
+            errors.push(format!("Tag {} is required", tag));
+        }
+    }
+
+    for (tag, values) in tags {
+        let definition = match registry.get(tag) {
+            Some(definition) => definition,
+            // The parent tag is understood by spiderman itself and needn't be declared
+            None if tag == PARENT_TAG || !check_unknown => continue,
+            None => {
+                errors.push(format!(
+                    "Unknown tag {}, known tags are {}",
+                    tag,
+                    registry.keys().cloned().collect::<Vec<_>>().join(", ")
+                ));
+                continue;
+            }
+        };
+
+        if !definition.multiple && values.len() > 1 {
+            errors.push(format!("Tag {} takes only one value", tag));
+        }
+
+        let pattern = match definition.pattern() {
+            Some(Ok(pattern)) => Some(pattern),
+            Some(Err(e)) => {
+                errors.push(format!(
+                    "Invalid pattern for tag {} in schema.toml: {}",
+                    tag, e
+                ));
+                None
+            }
+            None => None,
+        };
+
+        for value in values {
+            if !definition.tag_type.accepts(value) {
+                errors.push(format!(
+                    "Value {} of tag {} is not {}",
+                    value,
+                    tag,
+                    definition.tag_type.description()
+                ));
+            }
+            if let Some(allowed) = &definition.values {
+                if !allowed.contains(value) {
+                    errors.push(format!(
+                        "Value {} of tag {} is not one of {}",
+                        value,
+                        tag,
+                        allowed.join(", ")
+                    ));
+                }
+            }
+            if let Some(pattern) = &pattern {
+                if !pattern.is_match(value) {
+                    errors.push(format!(
+                        "Value {} of tag {} does not match {}",
+                        value,
+                        tag,
+                        pattern.as_str()
+                    ));
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+impl TagDefinition {
+    /// Whether the definition only declares aliases for the tag and its values
+    fn is_alias_only(&self) -> bool {
+        self.description.is_none()
+            && !self.required
+            && self.multiple
+            && self.tag_type == TagType::String
+            && self.values.is_none()
+            && self.pattern.is_none()
+    }
+
+    /// The compiled `pattern`, which is only compiled once per loaded registry
+    fn pattern(&self) -> Option<&Result<Regex, regex::Error>> {
+        self.compiled_pattern
+            .get_or_init(|| self.pattern.as_deref().map(Regex::new))
+            .as_ref()
+    }
+
+    fn canonical_value(&self, value: &str) -> Option<String> {
+        let allowed_values = self.values.iter().flatten();
+        let aliased_values = self
+            .value_aliases
+            .iter()
+            .filter(|(_, aliases)| aliases.iter().any(|a| a.eq_ignore_ascii_case(value)));
+
+        allowed_values
+            .chain(self.value_aliases.keys())
+            .find(|v| v.eq_ignore_ascii_case(value))
+            .or(aliased_values.map(|(v, _)| v).next())
+            .cloned()
+    }
+}
+
+impl TagType {
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            TagType::String => true,
+            TagType::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+            TagType::Integer => value.parse::<i64>().is_ok(),
+            TagType::Boolean => value == "true" || value == "false",
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self {
+            TagType::String => "a string",
+            TagType::Date => "a date (YYYY-MM-DD)",
+            TagType::Integer => "an integer",
+            TagType::Boolean => "a boolean (true or false)",
+        }
+    }
+}
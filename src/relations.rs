@@ -0,0 +1,150 @@
+use crate::journal::Operation;
+use crate::Project;
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RelationKind {
+    /// The project needs the other one
+    DependsOn,
+    Related,
+    /// The project replaces the other one
+    Supersedes,
+}
+
+impl RelationKind {
+    pub fn name(&self) -> &str {
+        match self {
+            RelationKind::DependsOn => "depends-on",
+            RelationKind::Related => "related",
+            RelationKind::Supersedes => "supersedes",
+        }
+    }
+
+    /// Name of the relation as seen from the other project
+    pub fn inverse_name(&self) -> &str {
+        match self {
+            RelationKind::DependsOn => "required-by",
+            RelationKind::Related => "related",
+            RelationKind::Supersedes => "superseded-by",
+        }
+    }
+}
+
+/// A relation of a project to another one, stored in `spiderman.toml` of the first project
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relation {
+    pub kind: RelationKind,
+    pub project: Uuid,
+}
+
+/// Adds a relation of `kind` from `from` to `to`, or removes it if `remove` is set. Returns the
+/// operations reversing the change, which are empty if there was nothing to change.
+pub fn set_relation(
+    from: &Project,
+    to: &Project,
+    kind: RelationKind,
+    remove: bool,
+) -> Result<Vec<Operation>> {
+    let relation = Relation {
+        kind,
+        project: to.uuid,
+    };
+
+    from.update_metadata(|metadata| {
+        if remove {
+            metadata.relations.retain(|r| *r != relation);
+        } else if !metadata.relations.contains(&relation) {
+            metadata.relations.push(relation.clone());
+        }
+    })
+}
+
+/// All relations between `projects` in both directions, as the index of a project, the name of
+/// the relation seen from that project and the index of the other project. Relations to projects
+/// not among `projects` are left out.
+fn edges(projects: &[Project]) -> Vec<(usize, &str, usize)> {
+    let indices: HashMap<Uuid, usize> = projects
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.uuid, i))
+        .collect();
+
+    let mut edges = vec![];
+    for (from, project) in projects.iter().enumerate() {
+        for relation in &project.metadata.relations {
+            if let Some(&to) = indices.get(&relation.project) {
+                edges.push((from, relation.kind.name(), to));
+                edges.push((to, relation.kind.inverse_name(), from));
+            }
+        }
+    }
+
+    edges
+}
+
+/// Warns about relations of `projects` to projects that are not among them
+pub fn warn_missing(projects: &[Project]) {
+    let uuids: HashSet<Uuid> = projects.iter().map(|p| p.uuid).collect();
+    for project in projects {
+        for relation in &project.metadata.relations {
+            if !uuids.contains(&relation.project) {
+                eprintln!(
+                    "WARNING: Project {} is {} project {}, which does not exist",
+                    project.name,
+                    relation.kind.name(),
+                    relation.project.hyphenated()
+                );
+            }
+        }
+    }
+}
+
+/// The relations of `project` to other projects among `projects`, including those other projects
+/// have to it
+pub fn relations_of<'a>(project: &Project, projects: &'a [Project]) -> Vec<(String, &'a Project)> {
+    let edges = edges(projects);
+    edges
+        .into_iter()
+        .filter(|(from, _, _)| projects[*from].uuid == project.uuid)
+        .map(|(_, kind, to)| (kind.to_string(), &projects[to]))
+        .collect()
+}
+
+/// Links of the relations view in `view_dir` along with the index of the project each one points
+/// to. Every project with relations gets a directory there, holding a directory for each kind of
+/// relation with links to the related projects.
+pub fn view_links(projects: &[Project], view_dir: &Path) -> Vec<(PathBuf, usize)> {
+    // Projects sharing a name are told apart by their UUID
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for project in projects {
+        *name_counts.entry(&project.name).or_default() += 1;
+    }
+    let entry_name = |project: &Project| {
+        if name_counts[project.name.as_str()] > 1 {
+            format!(
+                "{}.{}",
+                project.name,
+                &project.uuid.hyphenated().to_string()[..8]
+            )
+        } else {
+            project.name.clone()
+        }
+    };
+
+    edges(projects)
+        .into_iter()
+        .map(|(from, kind, to)| {
+            let link = view_dir
+                .join(entry_name(&projects[from]))
+                .join(kind)
+                .join(entry_name(&projects[to]));
+            (link, to)
+        })
+        .collect()
+}
@@ -0,0 +1,352 @@
+use crate::file_utils::write_atomically;
+use crate::journal::{Operation, Plan};
+use crate::metadata::{read_project_file, write_project_file, Tags};
+use crate::{Environment, Project};
+use anyhow::{Context, Result};
+use toml_edit::{Document, Item, TableLike, Value};
+
+/// A change of tag names or values across all projects and `schema.toml`
+#[derive(Debug, Clone)]
+pub enum TagChange {
+    RenameKey {
+        old: String,
+        new: String,
+    },
+    /// Replaces each of `values` of the tag `tag` by `into`, which covers renaming a value as
+    /// well as merging several values
+    ReplaceValues {
+        tag: String,
+        values: Vec<String>,
+        into: String,
+    },
+}
+
+/// The files a [`TagChange`] will modify, computed before anything is written
+pub struct RetagPlan {
+    change: TagChange,
+    projects: Vec<(Project, Tags)>,
+    schema_before: String,
+    schema_after: String,
+}
+
+impl TagChange {
+    fn apply_to_tags(&self, tags: &Tags) -> Tags {
+        let mut tags = tags.clone();
+        match self {
+            TagChange::RenameKey { old, new } => {
+                if let Some(values) = tags.remove(old) {
+                    let new_values = tags.entry(new.clone()).or_default();
+                    for value in values {
+                        if !new_values.contains(&value) {
+                            new_values.push(value);
+                        }
+                    }
+                }
+            }
+            TagChange::ReplaceValues { tag, values, into } => {
+                if let Some(tag_values) = tags.get_mut(tag) {
+                    let mut replaced: Vec<String> = vec![];
+                    for value in tag_values.drain(..) {
+                        let value = if values.contains(&value) {
+                            into.clone()
+                        } else {
+                            value
+                        };
+                        if !replaced.contains(&value) {
+                            replaced.push(value);
+                        }
+                    }
+                    *tag_values = replaced;
+                }
+            }
+        }
+
+        tags
+    }
+
+    /// Updates schema placeholders, `default_tag_values` and the tag registry, keeping the
+    /// formatting and comments of the file
+    fn apply_to_schema(&self, document: &mut Document) {
+        match self {
+            TagChange::RenameKey { old, new } => {
+                let old_placeholder = format!("{{{}}}", old);
+                let new_placeholder = format!("{{{}}}", new);
+                for_each_schema_path(document, |path| {
+                    let components: Vec<_> = path
+                        .split('/')
+                        .map(|c| {
+                            if c == old_placeholder {
+                                new_placeholder.as_str()
+                            } else {
+                                c
+                            }
+                        })
+                        .collect();
+                    let renamed = components.join("/");
+                    (renamed != path).then_some(renamed)
+                });
+
+                let rename_key = |table: &mut dyn TableLike| {
+                    if !table.contains_key(new) {
+                        if let Some(item) = table.remove(old) {
+                            table.insert(new, item);
+                        }
+                    }
+                };
+                for_each_default_values(document, rename_key);
+                if let Some(table) = document.get_mut("tags").and_then(|t| t.as_table_like_mut()) {
+                    rename_key(table);
+                }
+            }
+            TagChange::ReplaceValues { tag, values, into } => {
+                let replace = |v: &str| values.iter().any(|o| o == v).then(|| into.clone());
+                for_each_default_values(document, |defaults| {
+                    match defaults.get_mut(tag).and_then(|i| i.as_value_mut()) {
+                        Some(Value::Array(default_values)) => {
+                            for value in default_values.iter_mut() {
+                                replace_string(value, replace);
+                            }
+                            dedup_array(default_values);
+                        }
+                        Some(value) => replace_string(value, replace),
+                        None => {}
+                    }
+                });
+
+                let definition = document
+                    .get_mut("tags")
+                    .and_then(|t| t.as_table_like_mut())
+                    .and_then(|t| t.get_mut(tag))
+                    .and_then(|t| t.as_table_like_mut());
+                if let Some(definition) = definition {
+                    let allowed = definition.get_mut("values").and_then(|i| i.as_array_mut());
+                    if let Some(allowed) = allowed {
+                        for value in allowed.iter_mut() {
+                            replace_string(value, |v| {
+                                values.iter().any(|o| o == v).then(|| into.clone())
+                            });
+                        }
+                        dedup_array(allowed);
+                    }
+
+                    let aliases = definition
+                        .get_mut("value_aliases")
+                        .and_then(|t| t.as_table_like_mut());
+                    if let Some(aliases) = aliases {
+                        for value in values {
+                            if let Some(item) = aliases.remove(value) {
+                                match aliases.get_mut(into).and_then(|i| i.as_array_mut()) {
+                                    Some(into_aliases) => {
+                                        if let Some(moved) = item.as_array() {
+                                            into_aliases.extend(moved.iter().cloned());
+                                        }
+                                        dedup_array(into_aliases);
+                                    }
+                                    None => {
+                                        aliases.insert(into, item);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// How the change is shown in the history
+    pub fn description(&self) -> String {
+        match self {
+            TagChange::RenameKey { old, new } => format!("tags rename-key {} {}", old, new),
+            TagChange::ReplaceValues { tag, values, into } if values.len() == 1 => {
+                format!("tags rename-value {} {} {}", tag, values[0], into)
+            }
+            TagChange::ReplaceValues { tag, values, into } => format!(
+                "tags merge-values {} {} --into {}",
+                tag,
+                values.join(" "),
+                into
+            ),
+        }
+    }
+}
+
+/// Calls `rename` for the path of every schema, replacing it if a new path is returned
+fn for_each_schema_path(document: &mut Document, rename: impl Fn(&str) -> Option<String>) {
+    match document.get_mut("schemas") {
+        Some(Item::Value(Value::Array(schemas))) => {
+            for schema in schemas.iter_mut() {
+                match schema {
+                    Value::InlineTable(table) => {
+                        if let Some(path) = table.get_mut("path") {
+                            replace_string(path, &rename);
+                        }
+                    }
+                    value => replace_string(value, &rename),
+                }
+            }
+        }
+        Some(Item::ArrayOfTables(schemas)) => {
+            for table in schemas.iter_mut() {
+                if let Some(path) = table.get_mut("path").and_then(|p| p.as_value_mut()) {
+                    replace_string(path, &rename);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Calls `f` for the global `default_tag_values` and those of every schema
+fn for_each_default_values(document: &mut Document, f: impl Fn(&mut dyn TableLike)) {
+    let call = |table: Option<&mut dyn TableLike>| {
+        if let Some(defaults) = table
+            .and_then(|t| t.get_mut("default_tag_values"))
+            .and_then(|d| d.as_table_like_mut())
+        {
+            f(defaults);
+        }
+    };
+
+    call(Some(document.as_table_mut()));
+    match document.get_mut("schemas") {
+        Some(Item::Value(Value::Array(schemas))) => {
+            for schema in schemas.iter_mut() {
+                call(
+                    schema
+                        .as_inline_table_mut()
+                        .map(|t| t as &mut dyn TableLike),
+                );
+            }
+        }
+        Some(Item::ArrayOfTables(schemas)) => {
+            for table in schemas.iter_mut() {
+                call(Some(table));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces a string value, keeping the whitespace and comments around it
+fn replace_string(value: &mut Value, replace: impl Fn(&str) -> Option<String>) {
+    if let Some(new) = value.as_str().and_then(replace) {
+        let decor = value.decor().clone();
+        *value = Value::from(new);
+        *value.decor_mut() = decor;
+    }
+}
+
+fn dedup_array(array: &mut toml_edit::Array) {
+    let mut seen = vec![];
+    let mut i = 0;
+    while i < array.len() {
+        let value = array.get(i).unwrap().as_str().map(|s| s.to_string());
+        if value.is_some() && seen.contains(&value) {
+            array.remove(i);
+        } else {
+            seen.push(value);
+            i += 1;
+        }
+    }
+}
+
+impl RetagPlan {
+    pub fn new(change: TagChange) -> Result<Self> {
+        let env = Environment::get()?;
+
+        let projects = Project::list()?
+            .filter_map(|project| {
+                let tags = change.apply_to_tags(&project.own_tags);
+                (tags != project.own_tags).then_some((project, tags))
+            })
+            .collect();
+
+        let schema_before = std::fs::read_to_string(env.schema_file_path())?;
+        let mut document: Document = schema_before
+            .parse()
+            .context("Failed to parse schema.toml")?;
+        change.apply_to_schema(&mut document);
+        let schema_after = document.to_string();
+
+        Ok(Self {
+            change,
+            projects,
+            schema_before,
+            schema_after,
+        })
+    }
+}
+
+impl Plan for RetagPlan {
+    fn is_empty(&self) -> bool {
+        self.projects.is_empty() && self.schema_before == self.schema_after
+    }
+
+    fn print_preview(&self) {
+        for (project, tags) in &self.projects {
+            println!(
+                "{} ({}):",
+                project.name,
+                &project.uuid.hyphenated().to_string()[..8]
+            );
+            for (tag, values) in &project.own_tags {
+                if tags.get(tag) != Some(values) {
+                    println!("  - {}:{}", tag, values.join(":"));
+                }
+            }
+            for (tag, values) in tags {
+                if project.own_tags.get(tag) != Some(values) {
+                    println!("  + {}:{}", tag, values.join(":"));
+                }
+            }
+        }
+
+        if self.schema_before != self.schema_after {
+            println!("schema.toml:");
+            let before: Vec<_> = self.schema_before.lines().collect();
+            let after: Vec<_> = self.schema_after.lines().collect();
+            for line in before.iter().filter(|l| !after.contains(l)) {
+                println!("  - {}", line);
+            }
+            for line in after.iter().filter(|l| !before.contains(l)) {
+                println!("  + {}", line);
+            }
+        }
+    }
+
+    fn confirmation(&self) -> String {
+        format!("Change {} projects?", self.projects.len())
+    }
+
+    fn description(&self) -> String {
+        self.change.description()
+    }
+
+    fn apply_each(&self, operations: &mut Vec<Operation>) -> Result<()> {
+        let env = Environment::get()?;
+
+        if self.schema_before != self.schema_after {
+            write_atomically(&env.schema_file_path(), self.schema_after.as_bytes())?;
+            operations.push(Operation::EditSchema {
+                previous: self.schema_before.clone(),
+            });
+        }
+
+        for (project, tags) in &self.projects {
+            let project_dir = env
+                .raw_storage_dir
+                .join(project.uuid.hyphenated().to_string());
+            let previous = std::fs::read_to_string(project.get_tags_file_path()?)?;
+            let (_, metadata) = read_project_file(&project_dir)?;
+            write_project_file(&project_dir, tags, &metadata)
+                .with_context(|| format!("Failed to update tags of {}", project.name))?;
+            operations.push(Operation::EditTags {
+                uuid: project.uuid,
+                previous,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -1,68 +1,170 @@
+use crate::backend::LinkBackendKind;
+use crate::metadata::{ProjectState, TagValues};
+use crate::project::PARENT_TAG;
+use crate::registry::TagRegistry;
 use crate::schema::SchemaPathComponent::Fixed;
 use crate::{Environment, Project};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
 use itertools::Itertools;
-use serde::de::Visitor;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{MapAccess, Visitor};
 use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Formatter;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Schemas {
     pub(crate) schemas: Vec<Schema>,
-    default_tag_values: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) link_backend: LinkBackendKind,
+    #[serde(default)]
+    pub(crate) link_style: LinkStyle,
+    /// Directory of the view tree listing the relations of every project, relative to the root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) relations_view: Option<PathBuf>,
+    pub(crate) default_tag_values: BTreeMap<String, TagValues>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) tags: TagRegistry,
+}
+
+/// How the symlinks in the view tree refer to the raw project data
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// Links contain the absolute path of the project data
+    #[default]
+    Absolute,
+    /// Links contain the path of the project data relative to the link's own location, so they
+    /// survive moving the project root
+    Relative,
 }
 
 impl Schemas {
     pub fn fill(&self, project: &Project) -> Result<Vec<PathBuf>> {
-        let mut error = None;
-        let filled_schemas = self
-            .schemas
-            .iter()
-            .filter_map(|s| {
-                let filled_schemas = s.fill(project, &self.default_tag_values);
-                match filled_schemas {
-                    Ok(s) => Some(s),
-                    Err(e) => {
-                        error = Some(e);
-                        None
-                    }
-                }
-            })
-            .flatten()
-            .collect();
+        Ok(self.fill_each(project)?.into_iter().flatten().collect())
+    }
 
-        match error {
-            None => Ok(filled_schemas),
-            Some(e) => Err(e),
-        }
+    /// Fills every schema separately, the result contains the paths of each schema in order
+    pub fn fill_each(&self, project: &Project) -> Result<Vec<Vec<PathBuf>>> {
+        self.schemas
+            .iter()
+            .map(|s| s.fill(project, &self.default_tag_values))
+            .collect()
     }
 }
 
 #[derive(Debug)]
 pub struct Schema {
     components: Vec<SchemaPathComponent>,
+    pub(crate) options: SchemaOptions,
 }
 
-impl Serialize for Schema {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        if let Some(string_repr) = self
-            .components
+/// Per-schema settings, only available when a schema is written as a table
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaOptions {
+    /// Link archived projects instead of active ones
+    #[serde(default)]
+    pub archive: bool,
+    #[serde(default)]
+    pub on_collision: CollisionPolicy,
+    #[serde(default)]
+    pub on_missing_tag: MissingTagPolicy,
+    /// Directory name used for missing tags by [`MissingTagPolicy::Placeholder`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<String>,
+    /// Default values for this schema only, taking precedence over the global ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub default_tag_values: BTreeMap<String, TagValues>,
+}
+
+/// What to do with a project that lacks a tag used in a schema and has no default value for it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingTagPolicy {
+    /// Use the placeholder, `unknown` unless configured otherwise
+    #[default]
+    Placeholder,
+    /// Leave the project out of this schema
+    Skip,
+    /// Abort weaving
+    Fail,
+}
+
+const DEFAULT_PLACEHOLDER: &str = "unknown";
+
+/// What a schema uses for a tag a project lacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback<'a> {
+    /// The schema's or the global default values, never empty
+    Default(&'a [String]),
+    /// There is no default, the missing tag policy applies
+    Missing(MissingTagPolicy),
+}
+
+/// What to do when several projects end up at the same path of the view tree
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    /// Append `.1`, `.2`, ... in the order projects are woven
+    #[default]
+    Counter,
+    /// The project linked there first keeps the path, all others get the first eight characters
+    /// of their UUID appended
+    Uuid,
+    /// The project linked there first keeps the path, all others get `.1`, `.2`, ... in the order
+    /// they were created
+    Created,
+    /// Turn the path into a directory with one entry per project, named by UUID prefix
+    Merge,
+    /// Abort weaving
+    Fail,
+}
+
+/// Table representation of a schema in `schema.toml`
+#[derive(Serialize, Deserialize)]
+struct SchemaTable {
+    path: String,
+    #[serde(flatten)]
+    options: SchemaOptions,
+}
+
+impl Schema {
+    fn path_string(&self) -> Option<String> {
+        self.components
             .iter()
             .map(|c| match c {
                 SchemaPathComponent::Tag(t) => {
                     format!("{{{}}}", t)
                 }
+                SchemaPathComponent::Parent => PARENT_COMPONENT.to_string(),
+                SchemaPathComponent::Created(format) => {
+                    format!("{{{}:{}}}", CREATED_COMPONENT, format)
+                }
                 Fixed(f) => f.to_string(),
             })
             .intersperse("/".to_string())
             .reduce(|s1, s2| s1 + &s2)
-        {
-            serializer.serialize_str(&string_repr)
+    }
+}
+
+impl Serialize for Schema {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if let Some(string_repr) = self.path_string() {
+            if self.options == SchemaOptions::default() {
+                serializer.serialize_str(&string_repr)
+            } else {
+                SchemaTable {
+                    path: string_repr,
+                    options: self.options.clone(),
+                }
+                .serialize(serializer)
+            }
         } else {
             Err(ser::Error::custom(
                 "empty schema has no valid string representation",
@@ -76,7 +178,7 @@ impl<'de> Deserialize<'de> for Schema {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(SchemaVisitor)
+        deserializer.deserialize_any(SchemaVisitor)
     }
 }
 
@@ -86,7 +188,7 @@ impl<'de> Visitor<'de> for SchemaVisitor {
     type Value = Schema;
 
     fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-        formatter.write_str("a schema string")
+        formatter.write_str("a schema string or a table with a schema path and options")
     }
 
     fn visit_str<E>(self, v: &str) -> std::result::Result<Self::Value, E>
@@ -95,6 +197,16 @@ impl<'de> Visitor<'de> for SchemaVisitor {
     {
         Ok(Schema::from(v))
     }
+
+    fn visit_map<A>(self, map: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let table = SchemaTable::deserialize(MapAccessDeserializer::new(map))?;
+        let mut schema = Schema::from(table.path.as_str());
+        schema.options = table.options;
+        Ok(schema)
+    }
 }
 
 impl From<&str> for Schema {
@@ -110,7 +222,14 @@ impl From<&str> for Schema {
         let components: Vec<SchemaPathComponent> = value
             .split('/')
             .map(|s| {
-                if s.starts_with('{') && s.ends_with('}') {
+                if s == PARENT_COMPONENT {
+                    SchemaPathComponent::Parent
+                } else if let Some(format) = s
+                    .strip_prefix(&format!("{{{}:", CREATED_COMPONENT))
+                    .and_then(|f| f.strip_suffix('}'))
+                {
+                    SchemaPathComponent::Created(format.to_string())
+                } else if s.starts_with('{') && s.ends_with('}') {
                     SchemaPathComponent::Tag(s[1..s.len() - 1].to_string())
                 } else {
                     SchemaPathComponent::Fixed(s.to_string())
@@ -118,7 +237,10 @@ impl From<&str> for Schema {
             })
             .collect();
 
-        Self { components }
+        Self {
+            components,
+            options: SchemaOptions::default(),
+        }
     }
 }
 
@@ -126,54 +248,182 @@ impl Schema {
     fn fill(
         &self,
         project: &Project,
-        default_tags: &HashMap<String, String>,
+        default_tags: &BTreeMap<String, TagValues>,
     ) -> Result<Vec<PathBuf>> {
         let mut paths = vec![];
 
-        // Get all possible combinations of tags
-        let tag_combinations = project
-            .tags
+        // Archived projects only appear in archive schemas, and only they
+        let archived = project.metadata.state == ProjectState::Archived;
+        if archived != self.options.archive {
+            return Ok(paths);
+        }
+
+        if project.tags.is_empty() {
+            eprintln!(
+                "WARNING: The project with UUID {} has no tags and could not be linked anywhere.",
+                project.uuid.hyphenated().to_string()
+            );
+            return Ok(paths);
+        }
+
+        // Collect the values of every tag used in this schema, ordered by tag name. Other tags
+        // would only produce the same paths over and over again.
+        let mut tag_values: Vec<(&String, &[String])> = vec![];
+        let placeholder = [self.placeholder().to_string()];
+        for tag in self.referenced_tags() {
+            let values = match project.tags.get(tag) {
+                Some(values) => values.as_slice(),
+                None => match self.fallback(tag, default_tags) {
+                    Fallback::Default(values) => values,
+                    Fallback::Missing(_)
+                        if self.use_placeholder(project, &format!("tag {}", tag))? =>
+                    {
+                        &placeholder
+                    }
+                    Fallback::Missing(_) => return Ok(paths),
+                },
+            };
+            tag_values.push((tag, values));
+        }
+        let parent: &str = match &project.parent_name {
+            Some(parent) => parent,
+            None if !self.has_parent_component() => "",
+            None if self.use_placeholder(project, "parent")? => &placeholder[0],
+            None => return Ok(paths),
+        };
+        // Without a known creation date, the placeholder takes the place of every date component
+        let created: Option<DateTime<Local>> = if self
+            .components
             .iter()
-            .map(|(tag, values)| values.iter().map(move |v| (tag, v)))
-            .multi_cartesian_product()
-            .map(|p| p.into_iter().collect::<HashMap<&String, &String>>());
+            .any(|c| matches!(c, SchemaPathComponent::Created(_)))
+        {
+            match project.created()? {
+                Some(created) => Some(created.into()),
+                None if self.use_placeholder(project, "creation date")? => None,
+                None => return Ok(paths),
+            }
+        } else {
+            None
+        };
+
+        // Get all possible combinations of these values
+        let tag_combinations: Vec<HashMap<&String, &String>> = if tag_values.is_empty() {
+            vec![HashMap::new()]
+        } else {
+            tag_values
+                .into_iter()
+                .map(|(tag, values)| values.iter().map(move |v| (tag, v)))
+                .multi_cartesian_product()
+                .map(|p| p.into_iter().collect())
+                .collect()
+        };
 
         // Resolve path for each of these combinations and push the result to paths
         for tags in tag_combinations {
             let mut path = Environment::get()?.base_path.clone();
             for component in &self.components {
                 match component {
-                    SchemaPathComponent::Tag(tag) => {
-                        let value: &str = tags.get(tag).map(|s| s.as_str())
-                            .unwrap_or_else(|| default_tags.get(tag).map(|s| s.as_str()).unwrap_or_else(|| {
-                                eprintln!("WARNING: Could not evaluate tag {} for project {:?} with default tags {:?}", tag, project, default_tags);
-                                "unknown"
-                            }));
-
-                        path.push(value);
-                    }
+                    SchemaPathComponent::Tag(tag) => path.push(tags[tag]),
+                    SchemaPathComponent::Parent => path.push(parent),
+                    SchemaPathComponent::Created(format) => match &created {
+                        Some(created) => path.push(format_date(created, format)?),
+                        None => path.push(&placeholder[0]),
+                    },
                     SchemaPathComponent::Fixed(fixed_part) => path.push(fixed_part),
                 }
             }
 
             path.push(&project.name);
-            paths.push(path);
+            // Repeated tag values lead to the same path more than once
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
         }
 
-        if paths.is_empty() {
-            eprintln!(
-                "WARNING: The project with UUID {} has no tags and could not be linked anywhere.",
-                project.uuid.hyphenated().to_string()
-            );
+        return Ok(paths);
+    }
+
+    /// What this schema uses for `tag` if a project lacks it. An empty list of default values
+    /// counts as no default, as it would leave the project out without notice.
+    pub fn fallback<'a>(
+        &'a self,
+        tag: &str,
+        default_tags: &'a BTreeMap<String, TagValues>,
+    ) -> Fallback<'a> {
+        match self
+            .options
+            .default_tag_values
+            .get(tag)
+            .or(default_tags.get(tag))
+            .map(|values| values.as_slice())
+        {
+            Some(values) if !values.is_empty() => Fallback::Default(values),
+            _ => Fallback::Missing(self.options.on_missing_tag),
         }
+    }
 
-        return Ok(paths);
+    /// The directory name used for missing tags by [`MissingTagPolicy::Placeholder`]
+    pub fn placeholder(&self) -> &str {
+        self.options
+            .placeholder
+            .as_deref()
+            .unwrap_or(DEFAULT_PLACEHOLDER)
     }
 
-    pub fn match_with_dir(&self, path: &Path) -> Result<Option<HashMap<String, String>>> {
+    /// Applies the missing tag policy for a project lacking `what`. Returns whether the
+    /// placeholder is used, otherwise the project is left out of this schema.
+    fn use_placeholder(&self, project: &Project, what: &str) -> Result<bool> {
+        match self.options.on_missing_tag {
+            MissingTagPolicy::Placeholder => {
+                eprintln!(
+                    "WARNING: Project {} has no {}, linking it as {}",
+                    project.name,
+                    what,
+                    self.placeholder()
+                );
+                Ok(true)
+            }
+            MissingTagPolicy::Skip => Ok(false),
+            MissingTagPolicy::Fail => Err(anyhow!(
+                "Project {} ({}) has no {}, which schema {} needs",
+                project.name,
+                project.uuid.hyphenated(),
+                what,
+                self.path()
+            )),
+        }
+    }
+
+    fn has_parent_component(&self) -> bool {
+        self.components
+            .iter()
+            .any(|c| matches!(c, SchemaPathComponent::Parent))
+    }
+
+    /// The path of the schema as written in `schema.toml`
+    pub fn path(&self) -> String {
+        self.path_string().unwrap_or_default()
+    }
+
+    pub fn components(&self) -> &[SchemaPathComponent] {
+        &self.components
+    }
+
+    /// The tags used in this schema, ordered by name
+    pub fn referenced_tags(&self) -> BTreeSet<&String> {
+        self.components
+            .iter()
+            .filter_map(|c| match c {
+                SchemaPathComponent::Tag(tag) => Some(tag),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn match_with_dir(&self, path: &Path) -> Result<Option<BTreeMap<String, String>>> {
         let env = Environment::get()?;
         let path = std::path::absolute(path)?;
-        let mut tags = HashMap::new();
+        let mut tags = BTreeMap::new();
         if let Ok(path) = path.strip_prefix(env.base_path.clone()) {
             if path.components().zip(self.components.iter()).fold(
                 true,
@@ -185,6 +435,14 @@ impl Schema {
                         );
                         matches
                     }
+                    SchemaPathComponent::Created(_) => matches,
+                    SchemaPathComponent::Parent => {
+                        tags.insert(
+                            PARENT_TAG.to_string(),
+                            path.as_os_str().to_string_lossy().to_string(),
+                        );
+                        matches
+                    }
                     Fixed(name) => {
                         matches && (name.as_str() == path.as_os_str().to_string_lossy().as_ref())
                     }
@@ -201,5 +459,20 @@ impl Schema {
 #[derive(Debug)]
 pub enum SchemaPathComponent {
     Tag(String),
+    /// The name of the parent project, written as `{@parent}`
+    Parent,
+    /// The creation date of the project in a `strftime` format, written as `{@created:%Y}`
+    Created(String),
     Fixed(String),
 }
+
+const PARENT_COMPONENT: &str = "{@parent}";
+const CREATED_COMPONENT: &str = "@created";
+
+fn format_date(date: &DateTime<Local>, format: &str) -> Result<String> {
+    if StrftimeItems::new(format).any(|i| i == Item::Error) {
+        return Err(anyhow!("Invalid date format {} in schema", format));
+    }
+
+    Ok(date.format(format).to_string())
+}
@@ -0,0 +1,247 @@
+use crate::find::project_path;
+use crate::journal::{Journal, Operation};
+use crate::{Environment, Project};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Kinds of candidates offered by the shell completions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionKind {
+    Commands,
+    /// Subcommands of `tags`
+    TagsCommands,
+    Names,
+    Uuids,
+    TagNames,
+    TagValues,
+}
+
+// The wrapper functions only rely on the hidden `__relocate` and `__complete` subcommands, so
+// the scripts keep working when spiderman is updated without re-running `shell-init`.
+
+const BASH_INIT: &str = r#"# spiderman shell integration, load with: eval "$(spiderman shell-init bash)"
+spiderman() {
+    if [ "$1" = cd ]; then
+        shift
+        spcd "$@"
+        return
+    fi
+
+    local spiderman_pwd="$PWD"
+    command spiderman "$@"
+    local spiderman_status=$?
+    if [ ! -d "$spiderman_pwd" ]; then
+        local spiderman_dir
+        spiderman_dir="$(command spiderman __relocate "$spiderman_pwd")" && [ -n "$spiderman_dir" ] && cd "$spiderman_dir"
+    fi
+    return $spiderman_status
+}
+
+spcd() {
+    local spiderman_dir
+    spiderman_dir="$(command spiderman find "$@")" && cd "$spiderman_dir"
+}
+
+_spiderman_complete() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" IFS=$'\n'
+    local -a kinds=()
+    COMPREPLY=()
+    if [ "${COMP_WORDS[0]}" = spcd ] || { [ "$COMP_CWORD" -gt 1 ] && [[ "${COMP_WORDS[1]}" =~ ^(find|cd)$ ]]; }; then
+        kinds=(names uuids tag-values)
+    elif [ "$COMP_CWORD" -eq 1 ]; then
+        kinds=(commands)
+    elif [ "${COMP_WORDS[1]}" = tags ]; then
+        case "$COMP_CWORD:${COMP_WORDS[2]}" in
+            2:*) kinds=(tags-commands) ;;
+            3:rename-key | 3:rename-value | 3:merge-values) kinds=(tag-names) ;;
+            *:rename-value | *:merge-values) kinds=(tag-values) ;;
+        esac
+    fi
+    if [ ${#kinds[@]} -gt 0 ]; then
+        COMPREPLY=($(compgen -W "$(command spiderman __complete "${kinds[@]}")" -- "$cur"))
+    fi
+}
+complete -o default -F _spiderman_complete spiderman spcd
+"#;
+
+const ZSH_INIT: &str = r#"# spiderman shell integration, load with: eval "$(spiderman shell-init zsh)"
+spiderman() {
+    if [ "$1" = cd ]; then
+        shift
+        spcd "$@"
+        return
+    fi
+
+    local spiderman_pwd="$PWD"
+    command spiderman "$@"
+    local spiderman_status=$?
+    if [ ! -d "$spiderman_pwd" ]; then
+        local spiderman_dir
+        spiderman_dir="$(command spiderman __relocate "$spiderman_pwd")" && [ -n "$spiderman_dir" ] && cd "$spiderman_dir"
+    fi
+    return $spiderman_status
+}
+
+spcd() {
+    local spiderman_dir
+    spiderman_dir="$(command spiderman find "$@")" && cd "$spiderman_dir"
+}
+
+_spiderman_complete() {
+    local -a candidates kinds
+    if [[ $words[1] == spcd ]] || { (( CURRENT > 2 )) && [[ $words[2] == (find|cd) ]]; }; then
+        kinds=(names uuids tag-values)
+    elif (( CURRENT == 2 )); then
+        kinds=(commands)
+    elif [[ $words[2] == tags ]]; then
+        case "$(( CURRENT - 1 )):$words[3]" in
+            2:*) kinds=(tags-commands) ;;
+            3:rename-key | 3:rename-value | 3:merge-values) kinds=(tag-names) ;;
+            *:rename-value | *:merge-values) kinds=(tag-values) ;;
+        esac
+    fi
+
+    if (( $#kinds )); then
+        candidates=("${(@f)$(command spiderman __complete $kinds)}")
+        compadd -a candidates
+    else
+        _files
+    fi
+}
+# compdef only exists once the completion system has been loaded with compinit
+if (( $+functions[compdef] )); then
+    compdef _spiderman_complete spiderman spcd
+fi
+"#;
+
+const FISH_INIT: &str = r#"# spiderman shell integration, load with: spiderman shell-init fish | source
+function spiderman
+    if test "$argv[1]" = cd
+        spcd $argv[2..-1]
+        return
+    end
+
+    set -l spiderman_pwd $PWD
+    command spiderman $argv
+    set -l spiderman_status $status
+    if not test -d $spiderman_pwd
+        set -l spiderman_dir (command spiderman __relocate $spiderman_pwd)
+        and test -n "$spiderman_dir"
+        and cd $spiderman_dir
+    end
+    return $spiderman_status
+end
+
+function spcd
+    set -l spiderman_dir (command spiderman find $argv)
+    and cd $spiderman_dir
+end
+
+complete -c spiderman -f -n __fish_use_subcommand -a '(command spiderman __complete commands)'
+complete -c spiderman -f -n '__fish_seen_subcommand_from find cd' -a '(command spiderman __complete names uuids tag-values)'
+complete -c spiderman -f -n '__fish_seen_subcommand_from tags; and test (count (commandline -opc)) -eq 2' -a '(command spiderman __complete tags-commands)'
+complete -c spiderman -f -n '__fish_seen_subcommand_from rename-key rename-value merge-values; and test (count (commandline -opc)) -eq 3' -a '(command spiderman __complete tag-names)'
+complete -c spiderman -f -n '__fish_seen_subcommand_from rename-value merge-values; and test (count (commandline -opc)) -gt 3' -a '(command spiderman __complete tag-values)'
+complete -c spcd -f -a '(command spiderman __complete names uuids tag-values)'
+"#;
+
+pub fn init_script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH_INIT,
+        Shell::Zsh => ZSH_INIT,
+        Shell::Fish => FISH_INIT,
+    }
+}
+
+/// Completion candidates of the given kinds, taken from the project index. `commands` and
+/// `tags_commands` are the names of the subcommands of spiderman and `tags`.
+pub fn completions(
+    kinds: &[CompletionKind],
+    commands: &[String],
+    tags_commands: &[String],
+) -> Result<BTreeSet<String>> {
+    let mut candidates: BTreeSet<String> = BTreeSet::new();
+    if kinds.contains(&CompletionKind::Commands) {
+        candidates.extend(commands.iter().cloned());
+    }
+    if kinds.contains(&CompletionKind::TagsCommands) {
+        candidates.extend(tags_commands.iter().cloned());
+    }
+    let is_command =
+        |k: &CompletionKind| matches!(k, CompletionKind::Commands | CompletionKind::TagsCommands);
+    if kinds.iter().all(is_command) {
+        return Ok(candidates);
+    }
+
+    for project in Project::list()? {
+        for kind in kinds {
+            match kind {
+                CompletionKind::Commands | CompletionKind::TagsCommands => {}
+                CompletionKind::Names => {
+                    candidates.insert(project.name.clone());
+                }
+                CompletionKind::Uuids => {
+                    candidates.insert(project.uuid.hyphenated().to_string());
+                }
+                CompletionKind::TagNames => candidates.extend(project.tags.keys().cloned()),
+                CompletionKind::TagValues => {
+                    candidates.extend(project.tags.values().flatten().cloned())
+                }
+            }
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Where the shell should continue after its current directory `old_pwd` was moved away by the
+/// most recent command, if it was moved into the project root.
+pub fn relocated_dir(old_pwd: &Path) -> Result<Option<PathBuf>> {
+    // The current directory is gone, so the project root is looked up from where it used to be
+    if let Some(existing) = old_pwd.ancestors().find(|a| a.is_dir()) {
+        std::env::set_current_dir(existing)?;
+    }
+
+    let env = Environment::get()?;
+    let journal = Journal::load()?;
+    let entry = match journal.entries().next() {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    // The shell's path may go through symlinks, while the journal records the path the source
+    // was moved from, so both forms are tried
+    let candidates = [old_pwd.to_path_buf(), physical_path(old_pwd)];
+    for operation in &entry.operations {
+        if let Operation::Move { uuid, source, .. } = operation {
+            let rest = candidates.iter().find_map(|c| c.strip_prefix(source).ok());
+            if let Some(rest) = rest {
+                let project =
+                    Project::open(&env.raw_storage_dir.join(uuid.hyphenated().to_string()))?;
+                return Ok(Some(project_path(&project, false)?.join(rest)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Resolves the symlinks in the longest existing prefix of `path`
+fn physical_path(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return canonical.join(path.strip_prefix(ancestor).unwrap());
+        }
+    }
+
+    path.to_path_buf()
+}
@@ -0,0 +1,176 @@
+use crate::file_utils::{copy_directory, symlink_dir};
+use crate::{Environment, Project};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+/// Prefix of the staging directories in the raw data directory, which are ignored when listing
+/// projects
+pub const STAGING_DIR_PREFIX: &str = ".incoming-";
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TransferOptions {
+    /// Keep the source directory instead of removing it
+    pub copy: bool,
+    /// Leave a symlink to the project data where the source directory used to be
+    pub link_back: bool,
+}
+
+/// Moves or copies the directory `source` into the project root as a new project.
+///
+/// The data is staged in the raw data directory first and only becomes a project once it has
+/// been copied completely. The source directory is removed only after that, so a failure at any
+/// point leaves the source untouched.
+pub fn transfer_into_root(
+    source: &Path,
+    tags: &BTreeMap<String, String>,
+    options: TransferOptions,
+) -> Result<Project> {
+    let env = Environment::get()?;
+    let source = std::path::absolute(source)?;
+
+    if source.is_symlink() || !source.is_dir() {
+        return Err(anyhow!(
+            "{} does not exist or is not a directory",
+            source.to_string_lossy()
+        ));
+    }
+    let canonical_source = source.canonicalize()?;
+    if canonical_source.starts_with(env.spiderman_dir.canonicalize()?)
+        || env.base_path.canonicalize()?.starts_with(&canonical_source)
+    {
+        return Err(anyhow!(
+            "{} is part of the spiderman directory or contains the project root",
+            source.to_string_lossy()
+        ));
+    }
+
+    let name = source
+        .file_name()
+        .ok_or(anyhow!("Source directory has no name"))?
+        .to_string_lossy()
+        .into_owned();
+
+    let uuid = Uuid::new_v4();
+    let staging_dir =
+        env.raw_storage_dir
+            .join(format!("{}{}", STAGING_DIR_PREFIX, uuid.hyphenated()));
+    let project_dir = env.raw_storage_dir.join(uuid.hyphenated().to_string());
+
+    std::fs::create_dir(&staging_dir)?;
+    let moved = match stage(&source, &staging_dir, &name, tags, options) {
+        Ok(moved) => moved,
+        Err(e) => {
+            if let Err(cleanup_error) = std::fs::remove_dir_all(&staging_dir) {
+                eprintln!(
+                    "WARNING: Failed to remove staging directory {}: {}",
+                    staging_dir.to_string_lossy(),
+                    cleanup_error
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    // Both directories are in the raw data directory, so this is atomic
+    if let Err(e) = std::fs::rename(&staging_dir, &project_dir) {
+        if moved {
+            std::fs::rename(staging_dir.join(&name), &source)?;
+        }
+        return Err(e.into());
+    }
+    let data_path = project_dir.join(&name);
+
+    if !options.copy && !moved {
+        std::fs::remove_dir_all(&source).with_context(|| {
+            format!(
+                "Project was created, but failed to remove {}",
+                source.to_string_lossy()
+            )
+        })?;
+    }
+
+    if options.link_back {
+        symlink_dir(&data_path, &source)?;
+    }
+
+    Project::open(&project_dir)
+}
+
+/// Puts the project data and tags file into the staging directory. Returns whether the source
+/// was moved rather than copied.
+fn stage(
+    source: &Path,
+    staging_dir: &Path,
+    name: &str,
+    tags: &BTreeMap<String, String>,
+    options: TransferOptions,
+) -> Result<bool> {
+    let data_path = staging_dir.join(name);
+
+    // The tags file is written first, nothing may fail after the source has been moved
+    Project::write_tags_file(&Project::find_tags_file(staging_dir), tags)?;
+
+    // Renaming is atomic, but only works within one file system
+    let moved = !options.copy && std::fs::rename(source, &data_path).is_ok();
+    if !moved {
+        std::fs::create_dir(&data_path)?;
+        copy_directory(source, &data_path)?;
+
+        let source_stats = DirStats::collect(source)?;
+        let copy_stats = DirStats::collect(&data_path)?;
+        // Copies are modified when they are written, only their contents are compared
+        if (source_stats.files, source_stats.bytes) != (copy_stats.files, copy_stats.bytes) {
+            return Err(anyhow!(
+                "Copy of {} is incomplete: expected {} files with {} bytes, got {} files with {} bytes",
+                source.to_string_lossy(),
+                source_stats.files,
+                source_stats.bytes,
+                copy_stats.files,
+                copy_stats.bytes
+            ));
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Number of files, their total size and the latest modification time in a directory tree or of
+/// a single file, not following symlinks. A symlink counts as a file of the length of its target
+/// path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirStats {
+    pub files: u64,
+    pub bytes: u64,
+    /// Nanoseconds since the Unix epoch, including the modification of directories
+    pub modified: u64,
+}
+
+impl DirStats {
+    /// Collects the stats of `path`, which is followed if it is a symlink itself
+    pub fn collect(path: &Path) -> Result<Self> {
+        let mut stats = Self::default();
+        stats.add(path, path.metadata()?)?;
+        Ok(stats)
+    }
+
+    fn add(&mut self, path: &Path, metadata: Metadata) -> Result<()> {
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        self.modified = self.modified.max(modified);
+        if metadata.is_dir() {
+            for entry in path.read_dir()? {
+                let path = entry?.path();
+                self.add(&path, path.symlink_metadata()?)?;
+            }
+        } else {
+            self.files += 1;
+            self.bytes += metadata.len();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::metadata::ProjectState;
+use crate::schema::{Fallback, MissingTagPolicy};
+use crate::{Environment, Project};
+use anyhow::Result;
+use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Usage of tags across all projects, compared with what the schemas expect
+#[derive(Debug, Default)]
+pub struct Vocabulary {
+    /// Number of projects using each tag, and each of its values
+    tags: BTreeMap<String, (usize, BTreeMap<String, usize>)>,
+    /// Projects lacking a tag a schema needs, by tag and what they get instead, along with the
+    /// schemas doing so
+    fallbacks: BTreeMap<(String, String), (BTreeSet<String>, Vec<String>)>,
+    /// Tags set by projects that no schema references
+    unreferenced: BTreeSet<String>,
+    /// Tags referenced by schemas that no project sets
+    unset: BTreeSet<String>,
+}
+
+impl Vocabulary {
+    pub fn collect() -> Result<Self> {
+        let schemas = &Environment::get()?.schema;
+        let placeholders: BTreeSet<String> = schemas
+            .schemas
+            .iter()
+            .flat_map(|s| s.referenced_tags())
+            .cloned()
+            .collect();
+
+        let mut vocabulary = Self::default();
+        for project in Project::list()? {
+            for (tag, values) in &project.tags {
+                let (count, value_counts) = vocabulary.tags.entry(tag.clone()).or_default();
+                *count += 1;
+                for value in values {
+                    *value_counts.entry(value.clone()).or_default() += 1;
+                }
+            }
+
+            // Projects without any tags aren't linked at all, so they don't fall back either
+            if project.tags.is_empty() {
+                continue;
+            }
+            let archived = project.metadata.state == ProjectState::Archived;
+            let mut fallbacks: BTreeMap<(String, String), BTreeSet<String>> = BTreeMap::new();
+            for schema in schemas
+                .schemas
+                .iter()
+                .filter(|s| s.options.archive == archived)
+            {
+                for tag in schema
+                    .referenced_tags()
+                    .into_iter()
+                    .filter(|t| !project.tags.contains_key(*t))
+                {
+                    let fallback = match schema.fallback(tag, &schemas.default_tag_values) {
+                        Fallback::Default(values) => format!("default {}", values.join(", ")),
+                        Fallback::Missing(MissingTagPolicy::Placeholder) => {
+                            format!("linked as {}", schema.placeholder())
+                        }
+                        Fallback::Missing(MissingTagPolicy::Skip) => "skipped".to_string(),
+                        Fallback::Missing(MissingTagPolicy::Fail) => "fails weaving".to_string(),
+                    };
+                    fallbacks
+                        .entry((tag.clone(), fallback))
+                        .or_default()
+                        .insert(schema.path());
+                }
+            }
+            for (key, schema_paths) in fallbacks {
+                let (all_schema_paths, projects) = vocabulary.fallbacks.entry(key).or_default();
+                all_schema_paths.extend(schema_paths);
+                projects.push(project.name.clone());
+            }
+        }
+
+        vocabulary.unreferenced = vocabulary
+            .tags
+            .keys()
+            .filter(|t| !placeholders.contains(*t))
+            .cloned()
+            .collect();
+        vocabulary.unset = placeholders
+            .into_iter()
+            .filter(|t| !vocabulary.tags.contains_key(t))
+            .collect();
+
+        Ok(vocabulary)
+    }
+
+    pub fn print(&self) {
+        println!("Tags:");
+        for (tag, (count, values)) in &self.tags {
+            println!(
+                "  {}  ({} project{})",
+                tag,
+                count,
+                if *count == 1 { "" } else { "s" }
+            );
+            for (value, count) in values {
+                println!("    {}  {}", value, count);
+            }
+        }
+
+        if !self.fallbacks.is_empty() {
+            println!("Projects without a tag the schemas use:");
+            for ((tag, fallback), (schema_paths, projects)) in &self.fallbacks {
+                println!(
+                    "  {} ({} in {}): {}",
+                    tag,
+                    fallback,
+                    schema_paths.iter().join(", "),
+                    projects.join(", ")
+                );
+            }
+        }
+
+        if !self.unreferenced.is_empty() {
+            println!("Tags no schema uses:");
+            for tag in &self.unreferenced {
+                println!("  {}", tag);
+            }
+        }
+
+        if !self.unset.is_empty() {
+            println!("Schema placeholders no project sets:");
+            for tag in &self.unset {
+                println!("  {{{}}}", tag);
+            }
+        }
+    }
+}
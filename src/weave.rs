@@ -1,7 +1,16 @@
+use crate::backend::{LinkBackend, LinkBackendKind};
+use crate::file_utils::normalize_path;
+use crate::index::ProjectIndex;
+use crate::relations;
+use crate::schema::CollisionPolicy;
 use crate::{Environment, Project};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs::DirEntry;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
 pub fn remove_symlinks() -> Result<()> {
     let env = Environment::get()?;
@@ -9,33 +18,45 @@ pub fn remove_symlinks() -> Result<()> {
     let base_path = &env.base_path;
     let spiderman_dir = &env.spiderman_dir;
 
+    // Clean up after every backend, not only the configured one, so that switching backends
+    // doesn't leave stale entries behind
+    let backends: Vec<_> = LinkBackendKind::ALL
+        .iter()
+        .map(|k| k.backend(env.schema.link_style))
+        .collect();
+    for backend in &backends {
+        backend.clear()?;
+    }
+
     for directory in base_path
         .read_dir()?
         .filter_map(|d| d.ok())
         .filter(|d| d.path() != *spiderman_dir)
     {
         if directory.metadata().is_ok_and(|m| m.is_dir()) {
-            remove_symlinks_impl(&directory)?;
+            remove_symlinks_impl(&directory, &backends)?;
         }
     }
 
     Ok(())
 }
 
-fn remove_symlinks_impl(dir: &DirEntry) -> Result<()> {
+fn remove_symlinks_impl(dir: &DirEntry, backends: &[Box<dyn LinkBackend>]) -> Result<()> {
     for entry in dir.path().read_dir()?.filter_map(|d| d.ok()) {
         let path = entry.path();
-        if path.is_symlink() {
-            let canonical_path = entry.path().canonicalize().unwrap();
-            let canonical_raw_data_dir_path =
-                Environment::get()?.raw_storage_dir.canonicalize().unwrap();
-
-            if canonical_path.starts_with(canonical_raw_data_dir_path) {
-                // Entry is managed by spiderman (points into raw data directory)
-                remove_symlink_dir(&path)?;
+        let mut managed_by = None;
+        for backend in backends {
+            if backend.is_managed(&path)? {
+                managed_by = Some(backend);
+                break;
             }
-        } else if path.is_dir() {
-            remove_symlinks_impl(&entry)?;
+        }
+
+        if let Some(backend) = managed_by {
+            // Entry is managed by spiderman (points into raw data directory)
+            backend.remove(&path)?;
+        } else if !path.is_symlink() && path.is_dir() {
+            remove_symlinks_impl(&entry, backends)?;
         }
     }
 
@@ -95,67 +116,244 @@ fn remove_empty_directories_impl(dir: &DirEntry) -> Result<()> {
     Ok(())
 }
 
-pub fn construct_view_tree() -> Result<()> {
-    let env = Environment::get()?;
+/// A single entry of the view tree, linking a view path to the raw data directory of a project
+#[derive(Debug, Clone)]
+pub struct ViewLink {
+    pub uuid: Uuid,
+    pub link: PathBuf,
+    pub target: PathBuf,
+}
 
-    for project in Project::list()? {
-        let raw_data_path = project.get_project_raw_data_path()?;
+/// Projects that all want to be linked to the same path of the view tree
+struct LinkGroup {
+    path: PathBuf,
+    policy: CollisionPolicy,
+    members: Vec<usize>,
+}
 
-        for mut link_target in env.schema.fill(&project)? {
-            std::fs::create_dir_all(link_target.parent().unwrap())?;
+/// The view tree computed from all projects and schemas, before collisions with existing files
+/// are resolved
+pub struct ViewPlan {
+    projects: Vec<Project>,
+    groups: Vec<LinkGroup>,
+}
 
-            // Add a counter for duplicate link targets
-            let mut counter = 1;
-            while link_target.exists() {
-                if counter == 1 && link_target.extension().is_some() {
-                    link_target.set_extension(format!(
-                        "{}.{}",
-                        link_target.extension().unwrap().to_string_lossy(),
-                        counter
-                    ));
-                } else {
-                    link_target.set_extension(format!("{}", counter));
+impl ViewPlan {
+    /// Warns about relations to projects that don't exist, which are left out of the view tree
+    pub fn warn_missing_relations(&self) {
+        relations::warn_missing(&self.projects);
+    }
+
+    /// Fills all schemas for all projects without touching the view tree.
+    ///
+    /// Fails if projects collide at a path of a schema with [`CollisionPolicy::Fail`].
+    pub fn new() -> Result<Self> {
+        let env = Environment::get()?;
+        let projects: Vec<Project> = Project::list()?.collect();
+        let index = ProjectIndex::load()?;
+
+        // Group the projects by the path they want to be linked to, in the order the paths first
+        // appear
+        let mut groups: Vec<LinkGroup> = vec![];
+        let mut group_indices: HashMap<PathBuf, usize> = HashMap::new();
+        let filled_projects = projects
+            .par_iter()
+            .map(|p| env.schema.fill_each(p))
+            .collect::<Result<Vec<_>>>()?;
+        let mut add_to_group = |path: PathBuf, policy: CollisionPolicy, project_index: usize| {
+            if let Some(&i) = group_indices.get(&path) {
+                let members = &mut groups[i].members;
+                if !members.contains(&project_index) {
+                    members.push(project_index);
+                }
+            } else {
+                group_indices.insert(path.clone(), groups.len());
+                groups.push(LinkGroup {
+                    path,
+                    policy,
+                    members: vec![project_index],
+                });
+            }
+        };
+        for (project_index, filled_schemas) in filled_projects.into_iter().enumerate() {
+            for (schema, paths) in env.schema.schemas.iter().zip(filled_schemas) {
+                for path in paths {
+                    add_to_group(path, schema.options.on_collision, project_index);
                 }
-                counter += 1;
             }
+        }
+        if let Some(view_dir) = &env.schema.relations_view {
+            for (path, project_index) in
+                relations::view_links(&projects, &env.base_path.join(view_dir))
+            {
+                add_to_group(path, CollisionPolicy::Counter, project_index);
+            }
+        }
 
-            if counter != 1 {
-                eprintln!("Warning: Could not link project with UUID {} to proper target, as that target already exists. Linked to {} instead.",
-                          project.uuid.hyphenated().to_string(),
-                          link_target.to_string_lossy());
+        for group in &mut groups {
+            if group.members.len() < 2 {
+                continue;
             }
 
-            symlink_dir(&raw_data_path, &link_target)?;
+            match group.policy {
+                CollisionPolicy::Counter => {}
+                CollisionPolicy::Fail => {
+                    return Err(anyhow!(
+                        "Projects with UUIDs {} would all be linked to {}",
+                        group
+                            .members
+                            .iter()
+                            .map(|&i| projects[i].uuid.hyphenated().to_string())
+                            .join(", "),
+                        group.path.to_string_lossy()
+                    ));
+                }
+                _ => {
+                    // A project already linked to the path keeps it, the others follow in the
+                    // order they were created or first woven. Both are persisted, so projects
+                    // added later never take over an existing path.
+                    let relative_path = group.path.strip_prefix(&env.base_path)?;
+                    group.members.sort_by_cached_key(|&i| {
+                        let project = &projects[i];
+                        let entry = index.entry(&project.uuid);
+                        let incumbent =
+                            entry.is_some_and(|e| e.links.iter().any(|l| l == relative_path));
+                        let since = project
+                            .metadata
+                            .created
+                            .and_then(|c| c.timestamp_nanos_opt())
+                            .map(|t| t.max(0) as u64)
+                            .or(entry.map(|e| e.first_woven).filter(|&t| t > 0))
+                            .unwrap_or(u64::MAX);
+                        (!incumbent, since, project.uuid)
+                    });
+                }
+            }
         }
+
+        Ok(Self { projects, groups })
     }
 
-    Ok(())
+    /// Computes the final links of the view tree.
+    ///
+    /// `is_taken` is consulted in addition to the already resolved links when deciding whether a
+    /// link path is still free.
+    pub fn resolve(&self, is_taken: impl Fn(&Path) -> bool) -> Result<Vec<ViewLink>> {
+        let mut planned = HashSet::new();
+        let mut links = vec![];
+
+        for group in &self.groups {
+            let path = &group.path;
+            let mut counter = 0;
+            for (i, project) in group.members.iter().map(|&i| &self.projects[i]).enumerate() {
+                let link = match group.policy {
+                    CollisionPolicy::Counter | CollisionPolicy::Created => {
+                        // Add a counter for duplicate link targets
+                        let mut link;
+                        loop {
+                            link = if counter == 0 {
+                                path.clone()
+                            } else {
+                                with_suffix(path, &counter.to_string())
+                            };
+                            counter += 1;
+                            if !(planned.contains(&link) || is_taken(&link)) {
+                                break;
+                            }
+                        }
+
+                        if link != *path {
+                            eprintln!("Warning: Could not link project with UUID {} to proper target, as that target already exists. Linked to {} instead.",
+                                      project.uuid.hyphenated(),
+                                      link.to_string_lossy());
+                        }
+                        link
+                    }
+                    CollisionPolicy::Uuid if i > 0 => with_suffix(path, &uuid_prefix(project)),
+                    CollisionPolicy::Merge if group.members.len() > 1 => {
+                        path.join(uuid_prefix(project))
+                    }
+                    _ => path.clone(),
+                };
+
+                if planned.contains(&link) || is_taken(&link) {
+                    eprintln!(
+                        "Warning: Could not link project with UUID {} to {}, as that target already exists.",
+                        project.uuid.hyphenated(),
+                        link.to_string_lossy()
+                    );
+                    continue;
+                }
+
+                planned.insert(link.clone());
+                links.push(ViewLink {
+                    uuid: project.uuid,
+                    link,
+                    target: project.get_current_data_path()?,
+                });
+            }
+        }
+
+        // Projects are already woven in a stable order, sorting the result makes views of
+        // different weaves comparable line by line
+        links.sort_by(|a, b| a.link.cmp(&b.link));
+
+        Ok(links)
+    }
 }
 
-#[cfg(unix)]
-fn symlink_dir(source: &Path, dest: &Path) -> Result<()> {
-    std::os::unix::fs::symlink(source, dest)?;
+/// Appends `.suffix` to the last component of `path`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
 
-    Ok(())
+fn uuid_prefix(project: &Project) -> String {
+    project.uuid.hyphenated().to_string()[..8].to_string()
 }
 
-#[cfg(windows)]
-fn symlink_dir(source: &Path, dest: &Path) -> Result<()> {
-    std::os::windows::fs::symlink_dir(source, dest)?;
+pub fn construct_view_tree(plan: &ViewPlan) -> Result<()> {
+    let env = Environment::get()?;
+    let backend = env.schema.link_backend.backend(env.schema.link_style);
 
-    Ok(())
+    let links = plan.resolve(|p| backend.entry_path(p).exists())?;
+    backend.create_links(&links)?;
+
+    ProjectIndex::load()?.record_links(&links)
 }
 
-#[cfg(unix)]
-fn remove_symlink_dir(path: &Path) -> Result<()> {
-    std::fs::remove_file(path)?;
+/// Returns the path the symlink at `path` points to, if it points into the raw data directory.
+///
+/// Relative links are resolved against the directory containing the link, so links created with
+/// [`crate::schema::LinkStyle::Relative`] are recognised even if the project root has been moved.
+pub fn managed_link_target(path: &Path) -> Result<Option<PathBuf>> {
+    let target = path.read_link()?;
+    let target = if target.is_relative() {
+        path.parent().unwrap().join(target)
+    } else {
+        target
+    };
 
-    Ok(())
+    raw_data_target(&target)
 }
 
-#[cfg(windows)]
-fn remove_symlink_dir(path: &Path) -> Result<()> {
-    std::fs::remove_dir(path)?;
+/// Returns `target` if it lies within the raw data directory
+pub fn raw_data_target(target: &Path) -> Result<Option<PathBuf>> {
+    let env = Environment::get()?;
 
-    Ok(())
+    let normalized_target = normalize_path(target);
+    if normalized_target.starts_with(&env.raw_storage_dir) {
+        return Ok(Some(normalized_target));
+    }
+
+    // The link or the root may have been reached through other symlinks
+    if let Ok(canonical_target) = target.canonicalize() {
+        if canonical_target.starts_with(env.raw_storage_dir.canonicalize()?) {
+            return Ok(Some(canonical_target));
+        }
+    }
+
+    Ok(None)
 }